@@ -0,0 +1,448 @@
+use std::array;
+use std::sync::Arc;
+
+#[cfg(feature = "gtk")]
+use gtk::{pango::EllipsizeMode, prelude::WidgetExt, HeaderBar, IconSize, TextDirection, Widget};
+use smithay_client_toolkit::compositor::{CompositorState, Region, SurfaceData};
+use smithay_client_toolkit::reexports::client::protocol::wl_subsurface::WlSubsurface;
+use smithay_client_toolkit::reexports::client::protocol::wl_surface::WlSurface;
+use smithay_client_toolkit::reexports::client::{Dispatch, QueueHandle};
+use smithay_client_toolkit::reexports::csd_frame::{WindowManagerCapabilities, WindowState};
+use smithay_client_toolkit::reexports::protocols::wp::viewporter::client::{
+    wp_viewport::WpViewport, wp_viewporter::WpViewporter,
+};
+use smithay_client_toolkit::shell::WaylandSurface;
+use smithay_client_toolkit::shm::slot::SlotPool;
+use smithay_client_toolkit::shm::Shm;
+use smithay_client_toolkit::subcompositor::{SubcompositorState, SubsurfaceData};
+use tracing::{debug, warn};
+
+use crate::color_scheme::cached_color_scheme;
+use crate::layout::cached_button_layout;
+use crate::pointer::MouseState;
+use crate::shadow::ShadowSurface;
+#[cfg(not(feature = "gtk"))]
+use crate::HEADER_SIZE;
+use crate::{
+    button_states, decorations_disabled_by_env, init_shadow_surfaces_pos, BoundShm, ButtonKind,
+    ButtonLabels, Callback, GtkFrame, GtkFrameConfig, HeaderRenderer, TitlebarAction,
+    COMPACT_HEADER_SIZE, VISIBLE_BORDER_SIZE,
+};
+#[cfg(feature = "gtk")]
+use crate::{init_gtk, load_gtk_theme, natural_header_size, preload_gtk};
+
+/// Builder of [`GtkFrame`]
+///
+/// All the options have the same defaults as [`GtkFrame::new`]
+pub struct GtkFrameBuilder<'a, State> {
+    base_surface: WlSurface,
+    shm: &'a Shm,
+    sub_compositor: Arc<SubcompositorState>,
+    queue_handle: QueueHandle<State>,
+
+    #[cfg(feature = "gtk")]
+    gtk_init: bool,
+    preload: bool,
+    decorations: bool,
+    config: GtkFrameConfig,
+    resizable: bool,
+    scale_factor: f64,
+    wm_capabilities: WindowManagerCapabilities,
+    #[cfg(feature = "gtk")]
+    gtk_pump: Option<Callback<dyn FnMut()>>,
+    #[cfg(feature = "gtk")]
+    header_bar_hook: Option<Callback<dyn FnMut(&HeaderBar)>>,
+    on_button_activated: Option<Callback<dyn FnMut(ButtonKind) -> bool>>,
+    viewport_factory: Option<Box<dyn Fn(&WlSurface) -> WpViewport>>,
+    opaque_region: Option<Region>,
+    input_region: Option<Region>,
+    header_renderer: Option<Callback<dyn HeaderRenderer>>,
+}
+
+impl<'a, State> GtkFrameBuilder<'a, State> {
+    pub fn new(
+        base_surface: &impl WaylandSurface,
+        shm: &'a Shm,
+        sub_compositor: Arc<SubcompositorState>,
+        queue_handle: QueueHandle<State>,
+    ) -> Self {
+        Self {
+            base_surface: base_surface.wl_surface().clone(),
+            shm,
+            sub_compositor,
+            queue_handle,
+            #[cfg(feature = "gtk")]
+            gtk_init: true,
+            preload: true,
+            decorations: true,
+            config: GtkFrameConfig::default(),
+            resizable: true,
+            scale_factor: 1.0,
+            wm_capabilities: WindowManagerCapabilities::all(),
+            #[cfg(feature = "gtk")]
+            gtk_pump: None,
+            #[cfg(feature = "gtk")]
+            header_bar_hook: None,
+            on_button_activated: None,
+            viewport_factory: None,
+            opaque_region: None,
+            input_region: None,
+            header_renderer: None,
+        }
+    }
+
+    /// Whether to init gtk, gtk will be inited at most once
+    #[cfg(feature = "gtk")]
+    pub fn gtk_init(mut self, gtk_init: bool) -> Self {
+        self.gtk_init = gtk_init;
+        self
+    }
+
+    /// Whether to draw a throwaway header bar while building, default is true
+    ///
+    /// It loads the icon theme, the css and the fonts once per process, so the first draw of
+    /// the window doesn't hitch.
+    pub fn preload(mut self, preload: bool) -> Self {
+        self.preload = preload;
+        self
+    }
+
+    /// Whether to draw the decorations, default is true unless `SCTK_GTK_NO_DECORATIONS=1`
+    ///
+    /// Without the decorations the frame is built hidden and stays hidden, so the server can
+    /// decorate the window instead.
+    pub fn decorations(mut self, decorations: bool) -> Self {
+        self.decorations = decorations;
+        self
+    }
+
+    /// Set the sizes, timing and theme of the frame at once
+    pub fn config(mut self, config: GtkFrameConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Set a fixed header bar height, default is the natural height of the gtk header bar in
+    /// the current theme
+    pub fn header_size(mut self, header_size: u32) -> Self {
+        self.config.header_size = Some(header_size);
+        self
+    }
+
+    /// Set the icon size of the titlebar buttons, default picks it from the header bar height,
+    /// which is [`IconSize::Menu`] for the natural height
+    #[cfg(feature = "gtk")]
+    pub fn button_icon_size(mut self, icon_size: IconSize) -> Self {
+        self.config.button_icon_size = Some(icon_size);
+        self
+    }
+
+    /// Use a slim header bar, like the slim mode of elementary
+    ///
+    /// It is a preset of a 32px [`header_size`](Self::header_size), the 16px menu button icons
+    /// and the compact padding of [`GtkFrameConfig::compact`], later setters still override them.
+    pub fn compact(mut self) -> Self {
+        self.config.header_size = Some(COMPACT_HEADER_SIZE);
+        #[cfg(feature = "gtk")]
+        self.config.button_icon_size = Some(IconSize::Menu);
+        self.config.compact = true;
+        self
+    }
+
+    /// Set the border thickness around the window, it is clamped so the visible border always
+    /// fits in it.
+    pub fn border_size(mut self, border_size: u32) -> Self {
+        self.config.border_size = border_size;
+        self
+    }
+
+    /// Set the size of the resize grab area along the edges, default is 5
+    pub fn resize_edge_threshold(mut self, threshold: f64) -> Self {
+        self.config.resize_edge_threshold = threshold.max(0.0);
+        self
+    }
+
+    /// Set the action of the alternate click on the header bar, default is
+    /// [`TitlebarAction::Menu`]
+    pub fn alternate_click_action(mut self, action: TitlebarAction) -> Self {
+        self.config.alternate_click_action = action;
+        self
+    }
+
+    /// Set the action of the middle click on the header bar, default is
+    /// [`TitlebarAction::None`]
+    pub fn middle_click_action(mut self, action: TitlebarAction) -> Self {
+        self.config.middle_click_action = action;
+        self
+    }
+
+    /// Set the translated tooltips and accessible names of the titlebar buttons, default is
+    /// English
+    pub fn labels(mut self, labels: ButtonLabels) -> Self {
+        self.config.labels = labels;
+        self
+    }
+
+    /// Set the action of scrolling on the header bar, default is [`TitlebarAction::None`]
+    pub fn scroll_action(mut self, action: TitlebarAction) -> Self {
+        self.config.scroll_action = action;
+        self
+    }
+
+    /// Set whether to draw the drop shadow, default is true
+    pub fn shadow_enabled(mut self, shadow_enabled: bool) -> Self {
+        self.config.shadow_enabled = shadow_enabled;
+        self
+    }
+
+    /// Set whether the frame is resizable
+    pub fn resizable(mut self, resizable: bool) -> Self {
+        self.resizable = resizable;
+        self
+    }
+
+    /// Set the initial scale factor
+    pub fn scale_factor(mut self, scale_factor: f64) -> Self {
+        self.scale_factor = scale_factor.clamp(0.1, 64.);
+        self
+    }
+
+    /// Set the initial window manager capabilities
+    pub fn wm_capabilities(mut self, wm_capabilities: WindowManagerCapabilities) -> Self {
+        self.wm_capabilities = wm_capabilities;
+        self
+    }
+
+    /// Let the embedder pump the gtk main loop, see [`GtkFrame::set_gtk_pump`]
+    #[cfg(feature = "gtk")]
+    pub fn gtk_pump(mut self, gtk_pump: impl FnMut() + 'static) -> Self {
+        self.gtk_pump = Some(Callback(Box::new(gtk_pump)));
+        self
+    }
+
+    /// Add widgets to the header bar, see [`GtkFrame::set_header_bar_hook`]
+    #[cfg(feature = "gtk")]
+    pub fn header_bar_hook(mut self, hook: impl FnMut(&HeaderBar) + 'static) -> Self {
+        self.header_bar_hook = Some(Callback(Box::new(hook)));
+        self
+    }
+
+    /// Veto the titlebar button actions, see [`GtkFrame::set_on_button_activated`]
+    pub fn on_button_activated(mut self, hook: impl FnMut(ButtonKind) -> bool + 'static) -> Self {
+        self.on_button_activated = Some(Callback(Box::new(hook)));
+        self
+    }
+
+    /// Draw the header bar with `renderer` instead of gtk, or instead of [`SkiaHeaderRenderer`]
+    /// without the `gtk` feature, see [`GtkFrame::set_header_renderer`]
+    ///
+    /// [`SkiaHeaderRenderer`]: crate::SkiaHeaderRenderer
+    pub fn header_renderer(mut self, renderer: impl HeaderRenderer + 'static) -> Self {
+        self.header_renderer = Some(Callback(Box::new(renderer)));
+        self
+    }
+
+    /// Use `wp_viewporter` to map the buffers to the logical size, so the header bar can be
+    /// rendered at the fractional scale, see [`GtkFrame::set_fractional_scale`]
+    ///
+    /// Without it the frame falls back to the integer buffer scale.
+    pub fn viewporter(mut self, viewporter: &WpViewporter) -> Self
+    where
+        State: Dispatch<WpViewport, ()> + 'static,
+    {
+        let viewporter = viewporter.clone();
+        let queue_handle = self.queue_handle.clone();
+        self.viewport_factory = Some(Box::new(move |surface| {
+            viewporter.get_viewport(surface, &queue_handle, ())
+        }));
+        self
+    }
+
+    /// Use the compositor to set the opaque region of the header bar, when it has an opaque
+    /// custom background, and the input regions of the shadow surfaces, so the transparent
+    /// shadow away from the window doesn't take the input
+    pub fn compositor(mut self, compositor: &CompositorState) -> Self {
+        let (opaque_region, input_region) = match Region::new(compositor)
+            .and_then(|opaque_region| Ok((opaque_region, Region::new(compositor)?)))
+        {
+            Err(err) => {
+                warn!(%err, "create regions failed");
+
+                return self;
+            }
+
+            Ok(regions) => regions,
+        };
+
+        opaque_region.add(0, 0, i32::MAX, i32::MAX);
+        self.opaque_region = Some(opaque_region);
+        self.input_region = Some(input_region);
+        self
+    }
+
+    /// Build the [`GtkFrame`]
+    ///
+    /// # Errors
+    ///
+    /// if gtk init failed or gtk is not inited on this thread, such as on a display-less
+    /// machine. With a [`header_renderer`](Self::header_renderer) the frame doesn't need gtk, so
+    /// it is built without gtk instead. Without the `gtk` feature gtk is never used.
+    pub fn build(mut self) -> anyhow::Result<GtkFrame>
+    where
+        State: Dispatch<WlSurface, SurfaceData> + Dispatch<WlSubsurface, SubsurfaceData> + 'static,
+    {
+        #[cfg(feature = "gtk")]
+        let gtk_result = if self.gtk_init {
+            init_gtk()
+        } else if gtk::is_initialized_main_thread() {
+            Ok(())
+        } else {
+            Err(anyhow::anyhow!("gtk is not inited on this thread"))
+        };
+
+        #[cfg(feature = "gtk")]
+        let gtk_available = match gtk_result {
+            Ok(()) => true,
+
+            Err(err) if self.header_renderer.is_some() => {
+                warn!(%err, "build the frame without gtk, the header renderer draws it");
+
+                false
+            }
+
+            Err(err) => return Err(err),
+        };
+
+        #[cfg(feature = "gtk")]
+        load_gtk_theme();
+
+        let button_layout = cached_button_layout();
+
+        #[cfg(feature = "gtk")]
+        let header_size = self.config.header_size.unwrap_or_else(natural_header_size);
+        #[cfg(not(feature = "gtk"))]
+        let header_size = self.config.header_size.unwrap_or(HEADER_SIZE);
+
+        let decorations_disabled = !self.decorations || decorations_disabled_by_env();
+        if decorations_disabled {
+            debug!("decorations are disabled, build the frame hidden");
+        } else if self.preload {
+            #[cfg(feature = "gtk")]
+            preload_gtk();
+        }
+
+        let (subsurface, surface) = self
+            .sub_compositor
+            .create_subsurface(self.base_surface.clone(), &self.queue_handle);
+
+        subsurface.set_sync();
+
+        let viewport = self
+            .viewport_factory
+            .as_ref()
+            .map(|factory| factory(&surface));
+
+        let pool = SlotPool::new(1, self.shm)?;
+
+        let mut shadow_surfaces = array::from_fn(|_| {
+            let (subsurface, surface) = self
+                .sub_compositor
+                .create_subsurface(self.base_surface.clone(), &self.queue_handle);
+
+            let viewport = self
+                .viewport_factory
+                .as_ref()
+                .map(|factory| factory(&surface));
+
+            ShadowSurface {
+                surface,
+                subsurface,
+                viewport,
+                x: 0,
+                y: 0,
+                width: 0,
+                height: 0,
+                content: None,
+                attached: false,
+            }
+        });
+        self.config.border_size = self.config.border_size.max(VISIBLE_BORDER_SIZE);
+        init_shadow_surfaces_pos(&mut shadow_surfaces, header_size, self.config.border_size);
+
+        Ok(GtkFrame {
+            hidden: decorations_disabled,
+            decorations_disabled,
+            pool,
+            shm: BoundShm(self.shm.wl_shm().clone()),
+            dirty: !decorations_disabled,
+            button_damage: Vec::new(),
+            header_should_sync: true,
+            shadow_should_sync: true,
+            shadow_dirty: false,
+            resize_edge: None,
+            scale_factor: self.scale_factor,
+            output_scales: Vec::new(),
+            fractional_scale: None,
+            resizable: self.resizable,
+            config: self.config,
+            header_size,
+            width: None,
+            height: None,
+            left_buttons: button_states(button_layout.left_buttons),
+            right_buttons: button_states(button_layout.right_buttons),
+            button_layout_source: button_layout.source,
+            state: WindowState::empty(),
+            reported_active: false,
+            wm_capabilities: self.wm_capabilities,
+            mouse: MouseState {
+                header_size,
+                ..Default::default()
+            },
+            hovered_button: None,
+            #[cfg(feature = "gtk")]
+            header_widgets: None,
+            min_frame_width: 0,
+            title: String::new(),
+            title_prefix: None,
+            subtitle: None,
+            #[cfg(feature = "gtk")]
+            title_font: None,
+            #[cfg(feature = "gtk")]
+            window_icon: None,
+            header_theme: Default::default(),
+            #[cfg(feature = "gtk")]
+            custom_css: None,
+            header_renderer: self.header_renderer,
+            cursor_shape_device: None,
+            cursor_shape: None,
+            last_error: None,
+            #[cfg(feature = "gtk")]
+            direction: if gtk_available {
+                Widget::default_direction()
+            } else {
+                TextDirection::Ltr
+            },
+            color_scheme: cached_color_scheme(),
+            #[cfg(feature = "gtk")]
+            title_ellipsize: EllipsizeMode::Middle,
+            #[cfg(feature = "gtk")]
+            title_align: Default::default(),
+            #[cfg(feature = "gtk")]
+            title_button_gap: 0,
+            #[cfg(feature = "gtk")]
+            gtk_pump: self.gtk_pump,
+            #[cfg(feature = "gtk")]
+            header_bar_hook: self.header_bar_hook,
+            on_button_activated: self.on_button_activated,
+            header_bar_surface: surface,
+            header_bar_subsurface: subsurface,
+            header_bar_viewport: viewport,
+            header_bar_buffer: None,
+            opaque_region: self.opaque_region,
+            input_region: self.input_region,
+            shadow: Default::default(),
+            shadow_surfaces,
+            shadow_buffers: Default::default(),
+        })
+    }
+}
@@ -0,0 +1,93 @@
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+
+use tracing::{debug, warn};
+use zbus::zvariant::Value;
+
+use crate::portal;
+
+const COLOR_SCHEME_NAMESPACE: &str = "org.freedesktop.appearance";
+const COLOR_SCHEME_KEY: &str = "color-scheme";
+
+/// The detected color scheme, it is refreshed when the portal signals a change
+static COLOR_SCHEME: OnceLock<Mutex<ColorScheme>> = OnceLock::new();
+
+/// The system color scheme, from the `org.freedesktop.appearance` `color-scheme` setting
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum ColorScheme {
+    /// No preference, or the portal is not available
+    #[default]
+    NoPreference,
+    /// Prefer dark appearance
+    Dark,
+    /// Prefer light appearance
+    Light,
+}
+
+impl ColorScheme {
+    pub fn is_dark(&self) -> bool {
+        *self == ColorScheme::Dark
+    }
+}
+
+/// Get the cached color scheme, the first call detects the color scheme and starts watching the
+/// portal for changes
+pub fn cached_color_scheme() -> ColorScheme {
+    let color_scheme = COLOR_SCHEME.get_or_init(|| {
+        watch_color_scheme();
+
+        Mutex::new(get_color_scheme())
+    });
+
+    *color_scheme.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+fn watch_color_scheme() {
+    let result = thread::Builder::new()
+        .name("sctk_gtk color scheme watcher".to_string())
+        .spawn(|| {
+            let result = portal::watch_setting(COLOR_SCHEME_NAMESPACE, COLOR_SCHEME_KEY, || {
+                debug!("color scheme changed, refresh it");
+
+                let new_color_scheme = get_color_scheme();
+                if let Some(color_scheme) = COLOR_SCHEME.get() {
+                    *color_scheme.lock().unwrap_or_else(|err| err.into_inner()) = new_color_scheme;
+                }
+            });
+
+            if let Err(err) = result {
+                warn!(%err, "watch color scheme changes failed");
+            }
+        });
+
+    if let Err(err) = result {
+        warn!(%err, "spawn color scheme watcher thread failed");
+    }
+}
+
+fn get_color_scheme() -> ColorScheme {
+    let value = match portal::read_setting(COLOR_SCHEME_NAMESPACE, COLOR_SCHEME_KEY) {
+        Err(err) => {
+            warn!(%err, "read color scheme from XDG Settings Portal failed");
+
+            return ColorScheme::NoPreference;
+        }
+
+        Ok(value) => value,
+    };
+
+    let color_scheme = match &*value {
+        Value::U32(1) => ColorScheme::Dark,
+        Value::U32(2) => ColorScheme::Light,
+        Value::U32(_) => ColorScheme::NoPreference,
+        other => {
+            warn!(?other, "unknown color scheme");
+
+            ColorScheme::NoPreference
+        }
+    };
+
+    debug!(?color_scheme, "color scheme");
+
+    color_scheme
+}
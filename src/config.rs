@@ -0,0 +1,126 @@
+use std::time::Duration;
+
+#[cfg(feature = "gtk")]
+use gtk::IconSize;
+
+use crate::pointer::{ButtonKind, DOUBLE_CLICK_DURATION};
+use crate::shadow::Theme as ShadowTheme;
+use crate::{BORDER_SIZE, RESIZE_EDGE_THRESHOLD};
+
+/// The configuration of [`GtkFrame`](crate::GtkFrame)
+///
+/// All the sizes are in logical pixels.
+#[derive(Debug, Clone)]
+pub struct GtkFrameConfig {
+    /// The header bar height, `None` uses the natural height of the gtk header bar in the
+    /// current theme
+    pub header_size: Option<u32>,
+
+    /// The icon size of the titlebar buttons, `None` picks it from the header bar height, a
+    /// taller header bar gets larger icons
+    #[cfg(feature = "gtk")]
+    pub button_icon_size: Option<IconSize>,
+
+    /// Trim the padding of the header bar and shrink the titlebar buttons, so they fit a slim
+    /// header bar, see [`GtkFrameBuilder::compact`](crate::GtkFrameBuilder::compact)
+    pub compact: bool,
+
+    /// The border thickness around the window, includes the invisible resize area, the shadow
+    /// and the visible border. It is clamped so the visible border always fits in it.
+    pub border_size: u32,
+
+    /// Time to register the next click on the header bar as a double click
+    pub double_click_duration: Duration,
+
+    /// The shadow and visible border theme
+    pub shadow_theme: ShadowTheme,
+
+    /// Whether to draw the drop shadow and the visible border, the shadow surfaces are left
+    /// empty when disabled
+    pub shadow_enabled: bool,
+
+    /// The size of the resize grab area along the edges, it is multiplied by the scale factor
+    pub resize_edge_threshold: f64,
+
+    /// The action of the alternate click on the header bar
+    pub alternate_click_action: TitlebarAction,
+
+    /// The action of the middle click on the header bar, see
+    /// [`GtkFrame::on_middle_click`](crate::GtkFrame::on_middle_click)
+    pub middle_click_action: TitlebarAction,
+
+    /// The tooltips and accessible names of the titlebar buttons
+    pub labels: ButtonLabels,
+
+    /// The action of scrolling on the header bar, see
+    /// [`GtkFrame::on_scroll`](crate::GtkFrame::on_scroll)
+    pub scroll_action: TitlebarAction,
+}
+
+/// The action of a mouse button click on the header bar
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum TitlebarAction {
+    /// Do nothing
+    #[default]
+    None,
+    /// Move the window
+    Move,
+    /// Minimize the window
+    Minimize,
+    /// Show the window menu
+    Menu,
+}
+
+/// The tooltips and accessible names of the titlebar buttons, the default is English
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ButtonLabels {
+    pub close: String,
+    pub minimize: String,
+    pub maximize: String,
+    /// The maximize button of a maximized window
+    pub restore: String,
+}
+
+impl ButtonLabels {
+    /// The label of the button, `None` for the buttons without a label
+    pub fn label(&self, kind: ButtonKind, maximized: bool) -> Option<&str> {
+        match kind {
+            ButtonKind::Close => Some(&self.close),
+            ButtonKind::Minimize => Some(&self.minimize),
+            ButtonKind::Maximize if maximized => Some(&self.restore),
+            ButtonKind::Maximize => Some(&self.maximize),
+            ButtonKind::Menu | ButtonKind::Spacer | ButtonKind::Icon => None,
+        }
+    }
+}
+
+impl Default for ButtonLabels {
+    fn default() -> Self {
+        Self {
+            close: "Close".to_string(),
+            minimize: "Minimize".to_string(),
+            maximize: "Maximize".to_string(),
+            restore: "Restore".to_string(),
+        }
+    }
+}
+
+impl Default for GtkFrameConfig {
+    fn default() -> Self {
+        Self {
+            header_size: None,
+            #[cfg(feature = "gtk")]
+            button_icon_size: None,
+            compact: false,
+            border_size: BORDER_SIZE,
+            double_click_duration: DOUBLE_CLICK_DURATION,
+            shadow_theme: ShadowTheme::auto(),
+            shadow_enabled: true,
+            resize_edge_threshold: RESIZE_EDGE_THRESHOLD,
+            alternate_click_action: TitlebarAction::Menu,
+            middle_click_action: TitlebarAction::None,
+            scroll_action: TitlebarAction::None,
+            labels: ButtonLabels::default(),
+        }
+    }
+}
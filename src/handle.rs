@@ -0,0 +1,524 @@
+use std::collections::HashMap;
+use std::fmt::{self, Debug, Formatter};
+use std::num::NonZeroU32;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+use std::time::Duration;
+use std::{io, thread};
+
+use anyhow::Context;
+use smithay_client_toolkit::reexports::client::backend::ObjectId;
+use smithay_client_toolkit::reexports::csd_frame::{
+    CursorIcon, DecorationsFrame, FrameAction, FrameClick, WindowManagerCapabilities, WindowState,
+};
+use tracing::{error, warn};
+
+use crate::color_scheme::{cached_color_scheme, ColorScheme};
+use crate::GtkFrame;
+
+type Job<F> = Box<dyn FnOnce(&mut Frames<F>) + Send>;
+
+/// The jobs sender of the gtk thread, it is spawned by the first handle and hosts all the frames
+static GTK_THREAD: OnceLock<Result<Sender<Job<GtkFrame>>, String>> = OnceLock::new();
+
+/// The frames owned by a frame thread
+struct Frames<F> {
+    next_id: u64,
+    frames: HashMap<u64, F>,
+}
+
+impl<F> Default for Frames<F> {
+    fn default() -> Self {
+        Self {
+            next_id: 0,
+            frames: HashMap::new(),
+        }
+    }
+}
+
+/// A frame owned by a frame thread, the calls run on the thread in the order they are sent
+///
+/// The frame is dropped on its thread with the last reference.
+struct RemoteFrame<F: 'static> {
+    id: u64,
+    jobs: Sender<Job<F>>,
+}
+
+impl<F: 'static> RemoteFrame<F> {
+    /// Build the frame with `build` on the thread of `jobs` and wait for it
+    fn spawn(
+        jobs: Sender<Job<F>>,
+        build: impl FnOnce() -> anyhow::Result<F> + Send + 'static,
+    ) -> anyhow::Result<Self> {
+        let id = run_job(&jobs, move |frames| {
+            let frame = build()?;
+
+            let id = frames.next_id;
+            frames.next_id += 1;
+            frames.frames.insert(id, frame);
+
+            anyhow::Ok(id)
+        })??;
+
+        Ok(Self { id, jobs })
+    }
+
+    /// Run `f` with the frame on its thread and wait for the result
+    fn with_frame<R: Send + 'static>(
+        &self,
+        f: impl FnOnce(&mut F) -> R + Send + 'static,
+    ) -> anyhow::Result<R> {
+        let id = self.id;
+
+        run_job(&self.jobs, move |frames| {
+            frames
+                .frames
+                .get_mut(&id)
+                .map(f)
+                .context("the frame is gone from its thread")
+        })?
+    }
+
+    /// Run `f` with the frame on its thread without waiting
+    fn send(&self, f: impl FnOnce(&mut F) + Send + 'static) {
+        let id = self.id;
+        let job: Job<F> = Box::new(move |frames| {
+            if let Some(frame) = frames.frames.get_mut(&id) {
+                f(frame);
+            }
+        });
+
+        if self.jobs.send(job).is_err() {
+            warn!("the frame thread exited, drop the frame call");
+        }
+    }
+}
+
+impl<F: 'static> Debug for RemoteFrame<F> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RemoteFrame").field("id", &self.id).finish()
+    }
+}
+
+impl<F: 'static> Drop for RemoteFrame<F> {
+    fn drop(&mut self) {
+        let id = self.id;
+
+        // The frame is dropped on its thread, the handle doesn't wait for it.
+        let _ = self.jobs.send(Box::new(move |frames: &mut Frames<F>| {
+            frames.frames.remove(&id);
+        }));
+    }
+}
+
+/// Spawn a thread which owns the frames and runs the jobs sent to it, it exits once all the
+/// senders are dropped
+fn spawn_frame_thread<F: 'static>(name: &str) -> io::Result<Sender<Job<F>>> {
+    let (jobs_tx, jobs_rx) = mpsc::channel::<Job<F>>();
+
+    thread::Builder::new()
+        .name(name.to_string())
+        .spawn(move || {
+            let mut frames = Frames::default();
+
+            for job in jobs_rx {
+                // A panicking call fails only its own handle call, the other frames keep
+                // running.
+                if panic::catch_unwind(AssertUnwindSafe(|| job(&mut frames))).is_err() {
+                    error!("frame call panicked");
+                }
+            }
+        })?;
+
+    Ok(jobs_tx)
+}
+
+/// Run `f` on the thread of `jobs` and wait for its result
+fn run_job<F: 'static, R: Send + 'static>(
+    jobs: &Sender<Job<F>>,
+    f: impl FnOnce(&mut Frames<F>) -> R + Send + 'static,
+) -> anyhow::Result<R> {
+    let (result_tx, result_rx) = mpsc::sync_channel(1);
+    let job: Job<F> = Box::new(move |frames| {
+        let _ = result_tx.send(f(frames));
+    });
+
+    jobs.send(job)
+        .map_err(|_| anyhow::anyhow!("frame thread exited"))?;
+
+    result_rx
+        .recv()
+        .context("frame call panicked or the frame thread exited")
+}
+
+fn gtk_thread() -> anyhow::Result<Sender<Job<GtkFrame>>> {
+    GTK_THREAD
+        .get_or_init(|| spawn_frame_thread("sctk_gtk frame").map_err(|err| err.to_string()))
+        .clone()
+        .map_err(|err| anyhow::anyhow!("spawn gtk frame thread failed: {err}"))
+}
+
+/// The frame state the gtk thread publishes after every call, the handle reads it without
+/// waiting
+#[derive(Debug, Copy, Clone, Default)]
+struct Published {
+    dirty: bool,
+    /// The color scheme the frame is drawn with, `None` when it isn't drawn, such as when hidden
+    /// or suspended
+    drawn_color_scheme: Option<ColorScheme>,
+    /// The cursor of the last pointer motion the gtk thread handled
+    cursor_icon: Option<CursorIcon>,
+}
+
+impl Published {
+    /// Refresh the state from the frame, the cursor is only set by the pointer motion
+    fn update(&mut self, frame: &GtkFrame) {
+        self.dirty = frame.is_dirty();
+        self.drawn_color_scheme = (!frame.is_hidden()
+            && !frame.state.contains(WindowState::SUSPENDED))
+        .then(|| frame.color_scheme());
+    }
+}
+
+/// The frame state the borders depend on, the handle mirrors the calls which change it, so it is
+/// answered right away
+#[derive(Debug, Copy, Clone)]
+struct Geometry {
+    /// The header bar height, it is fixed once the frame is built
+    header_size: u32,
+    /// The frame stays hidden, like [`GtkFrame`] refuses to show it
+    decorations_disabled: bool,
+    hidden: bool,
+    fullscreen: bool,
+}
+
+impl Geometry {
+    fn new(frame: &GtkFrame) -> Self {
+        Self {
+            header_size: frame.header_size,
+            decorations_disabled: frame.decorations_disabled,
+            hidden: frame.hidden,
+            fullscreen: frame.state.contains(WindowState::FULLSCREEN),
+        }
+    }
+
+    /// The height the header bar adds above the window
+    fn header_height(&self) -> u32 {
+        if self.hidden || self.fullscreen {
+            0
+        } else {
+            self.header_size
+        }
+    }
+}
+
+/// A [`GtkFrame`] owned by the process wide gtk thread, the handle can be used from any thread
+///
+/// All the handles share one gtk thread, which runs the calls of a frame in the order they are
+/// sent. The gtk thread renders the decorations and attaches and commits the buffers to the
+/// decoration surfaces itself, the wayland objects can be used from any thread, so no buffers
+/// come back to the handle.
+///
+/// Only [`DecorationsFrame::draw`], [`DecorationsFrame::on_click`] and
+/// [`GtkFrameHandle::with_frame`] wait for the gtk thread, as they return its result, the other
+/// calls are sent without waiting. The location and the borders follow the calls right away.
+/// The dirty state is the one the gtk thread published after the calls it has run, and
+/// [`DecorationsFrame::click_point_moved`] returns the cursor of the motion handled before, so
+/// set a cursor shape device with [`GtkFrame::set_cursor_shape_device`] to let the frame set the
+/// exact cursor itself. Use [`GtkFrame`] directly when the wayland event loop runs on the gtk
+/// thread.
+///
+/// A [`DecorationsFrame`] call that can't reach the gtk thread, such as after a call panicked on
+/// it, logs the error and does nothing.
+#[derive(Debug)]
+pub struct GtkFrameHandle {
+    frame: RemoteFrame<GtkFrame>,
+    published: Arc<Mutex<Published>>,
+    geometry: Geometry,
+}
+
+impl GtkFrameHandle {
+    /// Build the frame on the gtk thread with `build`, the first call spawns the gtk thread
+    ///
+    /// The frames init gtk on the gtk thread, so the rest of the process must not use gtk.
+    /// `build` owns everything it needs to build the frame, such as the clones of the surface
+    /// and the queue handle.
+    pub fn spawn(
+        build: impl FnOnce() -> anyhow::Result<GtkFrame> + Send + 'static,
+    ) -> anyhow::Result<Self> {
+        let frame = RemoteFrame::spawn(gtk_thread()?, build)?;
+        let (published, geometry) = frame.with_frame(|frame| {
+            let mut published = Published::default();
+            published.update(frame);
+
+            (published, Geometry::new(frame))
+        })?;
+
+        Ok(Self {
+            frame,
+            published: Arc::new(Mutex::new(published)),
+            geometry,
+        })
+    }
+
+    /// Run `f` with the frame on the gtk thread and wait for its result, such as to call the
+    /// [`GtkFrame`] setters
+    ///
+    /// Return an error when the gtk thread is gone or `f` panicked.
+    pub fn with_frame<R: Send + 'static>(
+        &mut self,
+        f: impl FnOnce(&mut GtkFrame) -> R + Send + 'static,
+    ) -> anyhow::Result<R> {
+        let published = self.published.clone();
+        let (result, geometry) = self.frame.with_frame(move |frame| {
+            let result = f(frame);
+            lock(&published).update(frame);
+
+            (result, Geometry::new(frame))
+        })?;
+
+        self.geometry = geometry;
+
+        Ok(result)
+    }
+
+    /// Call `f` through [`Self::with_frame`], a failed call is logged and returns the default
+    fn call<R: Default + Send + 'static>(
+        &mut self,
+        f: impl FnOnce(&mut GtkFrame) -> R + Send + 'static,
+    ) -> R {
+        self.with_frame(f).unwrap_or_else(|err| {
+            warn!(%err, "gtk frame call failed");
+
+            R::default()
+        })
+    }
+
+    /// Run `f` with the frame on the gtk thread without waiting, the published state is
+    /// refreshed after it
+    fn send(&self, f: impl FnOnce(&mut GtkFrame) + Send + 'static) {
+        let published = self.published.clone();
+
+        self.frame.send(move |frame| {
+            f(frame);
+            lock(&published).update(frame);
+        });
+    }
+}
+
+fn lock(published: &Mutex<Published>) -> MutexGuard<'_, Published> {
+    published.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+impl DecorationsFrame for GtkFrameHandle {
+    fn on_click(
+        &mut self,
+        timestamp: Duration,
+        click: FrameClick,
+        pressed: bool,
+    ) -> Option<FrameAction> {
+        self.call(move |frame| frame.on_click(timestamp, click, pressed))
+    }
+
+    fn click_point_moved(
+        &mut self,
+        timestamp: Duration,
+        surface_id: &ObjectId,
+        x: f64,
+        y: f64,
+    ) -> Option<CursorIcon> {
+        let surface_id = surface_id.clone();
+        let published = self.published.clone();
+
+        self.frame.send(move |frame| {
+            let cursor_icon = frame.click_point_moved(timestamp, &surface_id, x, y);
+
+            let mut published = lock(&published);
+            published.update(frame);
+            published.cursor_icon = cursor_icon;
+        });
+
+        lock(&self.published).cursor_icon
+    }
+
+    fn click_point_left(&mut self) {
+        self.send(|frame| frame.click_point_left())
+    }
+
+    fn update_state(&mut self, state: WindowState) {
+        self.geometry.fullscreen = state.contains(WindowState::FULLSCREEN);
+
+        self.send(move |frame| frame.update_state(state))
+    }
+
+    fn update_wm_capabilities(&mut self, wm_capabilities: WindowManagerCapabilities) {
+        self.send(move |frame| frame.update_wm_capabilities(wm_capabilities))
+    }
+
+    fn resize(&mut self, width: NonZeroU32, height: NonZeroU32) {
+        self.send(move |frame| frame.resize(width, height))
+    }
+
+    fn set_scaling_factor(&mut self, scale_factor: f64) {
+        self.send(move |frame| frame.set_scaling_factor(scale_factor))
+    }
+
+    fn location(&self) -> (i32, i32) {
+        (0, -(self.geometry.header_height() as i32))
+    }
+
+    fn subtract_borders(
+        &self,
+        width: NonZeroU32,
+        height: NonZeroU32,
+    ) -> (Option<NonZeroU32>, Option<NonZeroU32>) {
+        let header_height = self.geometry.header_height();
+
+        (
+            Some(width),
+            NonZeroU32::new(height.get().saturating_sub(header_height)),
+        )
+    }
+
+    fn add_borders(&self, width: u32, height: u32) -> (u32, u32) {
+        (width, height.saturating_add(self.geometry.header_height()))
+    }
+
+    fn is_dirty(&self) -> bool {
+        if self.geometry.hidden {
+            return false;
+        }
+
+        // The color scheme changes without a call, like `GtkFrame::is_dirty` compare it here.
+        let published = *lock(&self.published);
+        published.dirty
+            || published
+                .drawn_color_scheme
+                .is_some_and(|color_scheme| color_scheme != cached_color_scheme())
+    }
+
+    fn set_hidden(&mut self, hidden: bool) {
+        // Like `GtkFrame::set_hidden`, a frame with disabled decorations stays hidden.
+        if hidden || !self.geometry.decorations_disabled {
+            self.geometry.hidden = hidden;
+        }
+
+        self.send(move |frame| frame.set_hidden(hidden))
+    }
+
+    fn is_hidden(&self) -> bool {
+        self.geometry.hidden
+    }
+
+    fn set_resizable(&mut self, resizable: bool) {
+        self.send(move |frame| frame.set_resizable(resizable))
+    }
+
+    fn draw(&mut self) -> bool {
+        self.call(|frame| frame.draw())
+    }
+
+    fn set_title(&mut self, title: impl Into<String>) {
+        let title = title.into();
+
+        self.send(move |frame| frame.set_title(title))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use super::*;
+
+    /// A frame which records that it was dropped
+    struct TestFrame {
+        value: u32,
+        dropped: Arc<AtomicBool>,
+    }
+
+    impl Drop for TestFrame {
+        fn drop(&mut self) {
+            self.dropped.store(true, Ordering::SeqCst);
+        }
+    }
+
+    fn spawn_test_frame(
+        jobs: &Sender<Job<TestFrame>>,
+    ) -> (RemoteFrame<TestFrame>, Arc<AtomicBool>) {
+        let dropped = Arc::new(AtomicBool::new(false));
+        let frame_dropped = dropped.clone();
+        let frame = RemoteFrame::spawn(jobs.clone(), move || {
+            Ok(TestFrame {
+                value: 0,
+                dropped: frame_dropped,
+            })
+        })
+        .unwrap();
+
+        (frame, dropped)
+    }
+
+    fn frame_count(jobs: &Sender<Job<TestFrame>>) -> usize {
+        run_job(jobs, |frames| frames.frames.len()).unwrap()
+    }
+
+    #[test]
+    fn calls_run_on_the_frame_in_order() {
+        let jobs = spawn_frame_thread("test frame").unwrap();
+        let (frame, _) = spawn_test_frame(&jobs);
+
+        frame.send(|frame| frame.value = 1);
+        frame.send(|frame| frame.value *= 10);
+        let value = frame
+            .with_frame(|frame| {
+                frame.value += 1;
+                frame.value
+            })
+            .unwrap();
+
+        assert_eq!(value, 11);
+    }
+
+    #[test]
+    fn failed_build_is_returned() {
+        let jobs = spawn_frame_thread::<TestFrame>("test frame").unwrap();
+
+        let result = RemoteFrame::spawn(jobs.clone(), || anyhow::bail!("no display"));
+
+        assert!(result.is_err());
+        assert_eq!(frame_count(&jobs), 0);
+    }
+
+    #[test]
+    fn panicking_call_leaves_the_other_frames_working() {
+        let jobs = spawn_frame_thread("test frame").unwrap();
+        let (panicking, _) = spawn_test_frame(&jobs);
+        let (other, _) = spawn_test_frame(&jobs);
+
+        assert!(panicking
+            .with_frame(|_| -> u32 { panic!("frame call panicked") })
+            .is_err());
+        panicking.send(|_| panic!("frame call panicked"));
+
+        assert_eq!(other.with_frame(|frame| frame.value + 1).unwrap(), 1);
+        assert_eq!(panicking.with_frame(|frame| frame.value).unwrap(), 0);
+    }
+
+    #[test]
+    fn dropped_handle_removes_the_frame() {
+        let jobs = spawn_frame_thread("test frame").unwrap();
+        let (frame, dropped) = spawn_test_frame(&jobs);
+        let (other, other_dropped) = spawn_test_frame(&jobs);
+        assert_eq!(frame_count(&jobs), 2);
+
+        drop(frame);
+
+        // The jobs run in order, so the frame is gone once a later job ran.
+        assert_eq!(frame_count(&jobs), 1);
+        assert!(dropped.load(Ordering::SeqCst));
+        assert!(!other_dropped.load(Ordering::SeqCst));
+        assert!(other.with_frame(|_| ()).is_ok());
+    }
+}
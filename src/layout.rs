@@ -1,90 +1,339 @@
-use std::process::Command;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::thread;
 
-use tracing::warn;
+use tracing::{debug, trace, warn};
 
 use crate::pointer::ButtonKind;
+use crate::portal;
+
+const BUTTON_LAYOUT_NAMESPACE: &str = "org.gnome.desktop.wm.preferences";
+const BUTTON_LAYOUT_KEY: &str = "button-layout";
+/// Overrides the detected button layout, such as for testing or desktops without the portal
+const BUTTON_LAYOUT_ENV: &str = "SCTK_GTK_BUTTON_LAYOUT";
+/// The kwin config file in the XDG config directory
+const KWIN_CONFIG_FILE: &str = "kwinrc";
+const KWIN_DECORATION_GROUP: &str = "[org.kde.kdecoration2]";
+/// The kwin defaults, the menu and on all desktops buttons at the start, the help, minimize,
+/// maximize and close buttons at the end
+const KWIN_DEFAULT_LEFT: &str = "MS";
+const KWIN_DEFAULT_RIGHT: &str = "HIAX";
+
+/// The detected button layout, it is refreshed when the portal signals a change, `None` is
+/// detected again on the next use
+static BUTTON_LAYOUT: OnceLock<Mutex<Option<ButtonLayout>>> = OnceLock::new();
+
+/// Where the button layout comes from
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum LayoutSource {
+    /// The `SCTK_GTK_BUTTON_LAYOUT` environment variable
+    EnvOverride,
+    /// The GNOME `button-layout` setting read from the XDG Settings Portal
+    GnomePortal,
+    /// The KDE KWin configuration
+    KdeConfig,
+    /// Set by the application
+    Manual,
+    /// No source is available, the builtin default is used
+    Default,
+}
+
+#[derive(Debug, Clone)]
+pub struct ButtonLayout {
+    /// Buttons at the start of frame, in `pack_start` order
+    pub left_buttons: Vec<ButtonKind>,
+    /// Buttons at the end of frame, in `pack_end` order
+    pub right_buttons: Vec<ButtonKind>,
+    pub source: LayoutSource,
+}
 
 /// Query system configuration for buttons layout.
 /// Should be updated to use standard xdg-desktop-portal specs once available
 /// https://github.com/flatpak/xdg-desktop-portal/pull/996
-fn get_button_layout_config() -> Option<(String, String)> {
-    let config_string = Command::new("dbus-send")
-        .arg("--reply-timeout=100")
-        .arg("--print-reply=literal")
-        .arg("--dest=org.freedesktop.portal.Desktop")
-        .arg("/org/freedesktop/portal/desktop")
-        .arg("org.freedesktop.portal.Settings.Read")
-        .arg("string:org.gnome.desktop.wm.preferences")
-        .arg("string:button-layout")
-        .output()
-        .ok()
-        .and_then(|out| String::from_utf8(out.stdout).ok())?;
-
-    let sides_split: Vec<_> = config_string
-        // Taking last word
-        .rsplit(' ')
-        .next()?
-        // Split by left/right side
-        .split(':')
-        // Only two sides
-        .take(2)
-        .collect();
-
-    match sides_split.as_slice() {
-        [left, right] => Some((left.to_string(), right.to_string())),
-        _ => None,
-    }
-}
-
-/// when bool is true, means buttons should be at the end of frame, otherwise at the start
-pub fn get_button_layout() -> (bool, Vec<ButtonKind>) {
-    match get_button_layout_config() {
-        None => {
-            warn!("get button layout config failed, use default config");
-
-            (
-                true,
-                vec![
-                    ButtonKind::Close,
-                    ButtonKind::Maximize,
-                    ButtonKind::Minimize,
-                ],
-            )
-        }
+fn get_button_layout_config() -> Option<String> {
+    let config_string =
+        match portal::read_string_setting(BUTTON_LAYOUT_NAMESPACE, BUTTON_LAYOUT_KEY) {
+            Err(err) => {
+                warn!(%err, "read button layout from XDG Settings Portal failed");
 
-        Some((left, right)) => {
-            let buttons = collect_buttons(&left);
-            if !buttons.is_empty() {
-                return (false, buttons);
+                return None;
             }
 
-            let buttons = collect_buttons(&right);
-            if !buttons.is_empty() {
-                return (true, buttons);
+            Ok(config_string) => config_string,
+        };
+
+    trace!(config_string, "read button layout config");
+
+    Some(config_string)
+}
+
+/// The button layout override from the environment, in the gtk format such as
+/// `icon:minimize,maximize,close`
+fn env_button_layout_config() -> Option<String> {
+    let config_string = env::var(BUTTON_LAYOUT_ENV).ok()?;
+
+    trace!(config_string, "read button layout from {BUTTON_LAYOUT_ENV}");
+
+    Some(config_string)
+}
+
+/// Get the cached button layout, the first call detects the layout and starts watching the
+/// portal for changes
+pub fn cached_button_layout() -> ButtonLayout {
+    let layout = BUTTON_LAYOUT.get_or_init(|| {
+        // The override never changes, so the portal is not needed at all.
+        if env_button_layout_config().is_none() {
+            watch_button_layout();
+        }
+
+        Mutex::new(None)
+    });
+
+    layout
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .get_or_insert_with(get_button_layout)
+        .clone()
+}
+
+/// Clear the cached button layout of the process, the next frame detects it again
+///
+/// The cache is shared by all the frames and refreshed when the portal signals a change, so
+/// it is only needed in tests.
+pub fn clear_button_layout_cache() {
+    if let Some(layout) = BUTTON_LAYOUT.get() {
+        *layout.lock().unwrap_or_else(|err| err.into_inner()) = None;
+    }
+}
+
+fn watch_button_layout() {
+    let result = thread::Builder::new()
+        .name("sctk_gtk button layout watcher".to_string())
+        .spawn(|| {
+            let result = portal::watch_setting(BUTTON_LAYOUT_NAMESPACE, BUTTON_LAYOUT_KEY, || {
+                debug!("button layout changed, refresh it");
+
+                let new_layout = get_button_layout();
+                if let Some(layout) = BUTTON_LAYOUT.get() {
+                    *layout.lock().unwrap_or_else(|err| err.into_inner()) = Some(new_layout);
+                }
+            });
+
+            if let Err(err) = result {
+                warn!(%err, "watch button layout changes failed");
             }
+        });
+
+    if let Err(err) = result {
+        warn!(%err, "spawn button layout watcher thread failed");
+    }
+}
+
+/// The button layout sources in priority order, the first one with a layout wins, add a
+/// desktop by adding its source here
+const LAYOUT_SOURCES: [fn() -> Option<ButtonLayout>; 3] =
+    [env_button_layout, gnome_button_layout, kde_button_layout];
+
+pub fn get_button_layout() -> ButtonLayout {
+    if let Some(layout) = LAYOUT_SOURCES.iter().find_map(|source| source()) {
+        return layout;
+    }
+
+    warn!("get button layout config failed, use default config");
+
+    ButtonLayout {
+        left_buttons: vec![],
+        right_buttons: vec![
+            ButtonKind::Close,
+            ButtonKind::Maximize,
+            ButtonKind::Minimize,
+        ],
+        source: LayoutSource::Default,
+    }
+}
+
+fn env_button_layout() -> Option<ButtonLayout> {
+    let config_string = env_button_layout_config()?;
+    let layout = parse_button_layout(&config_string, LayoutSource::EnvOverride);
+    if layout.is_none() {
+        warn!(
+            config_string,
+            "unknown button layout in {BUTTON_LAYOUT_ENV}, ignore it"
+        );
+    }
+
+    layout
+}
+
+fn gnome_button_layout() -> Option<ButtonLayout> {
+    let config_string = get_button_layout_config()?;
+    let layout = parse_button_layout(&config_string, LayoutSource::GnomePortal);
+    if layout.is_none() {
+        warn!(config_string, "unknown button layout config");
+    }
+
+    layout
+}
+
+fn kde_button_layout() -> Option<ButtonLayout> {
+    let config_dir = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+    let path = config_dir.join(KWIN_CONFIG_FILE);
+
+    let kwinrc = match fs::read_to_string(&path) {
+        Err(err) => {
+            trace!(%err, ?path, "read kwin config failed");
+
+            return None;
+        }
+
+        Ok(kwinrc) => kwinrc,
+    };
+
+    let (left, right) = read_kwin_buttons(&kwinrc)?;
+
+    debug!(left, right, "kwin button layout config");
+
+    let layout = parse_kwin_buttons(&left, &right);
+    if layout.is_none() {
+        warn!(left, right, "unknown kwin button layout config");
+    }
+
+    layout
+}
+
+/// Read the `ButtonsOnLeft` and `ButtonsOnRight` of the kwin decoration, the missing side
+/// uses the kwin default
+fn read_kwin_buttons(kwinrc: &str) -> Option<(String, String)> {
+    let mut in_section = false;
+    let mut left = None;
+    let mut right = None;
+
+    for line in kwinrc.lines().map(str::trim) {
+        if line.starts_with('[') {
+            in_section = line == KWIN_DECORATION_GROUP;
+
+            continue;
+        }
 
-            warn!("unknown button layout config, use default config");
+        if !in_section {
+            continue;
+        }
 
-            (
-                true,
-                vec![
-                    ButtonKind::Minimize,
-                    ButtonKind::Maximize,
-                    ButtonKind::Close,
-                ],
-            )
+        match line.split_once('=') {
+            Some(("ButtonsOnLeft", value)) => left = Some(value.trim().to_string()),
+            Some(("ButtonsOnRight", value)) => right = Some(value.trim().to_string()),
+            _ => {}
         }
     }
+
+    // Without any key kwin may not be the desktop at all.
+    if left.is_none() && right.is_none() {
+        return None;
+    }
+
+    Some((
+        left.unwrap_or_else(|| KWIN_DEFAULT_LEFT.to_string()),
+        right.unwrap_or_else(|| KWIN_DEFAULT_RIGHT.to_string()),
+    ))
+}
+
+/// Parse the kwin button letters of both sides, `None` when they have no supported button
+fn parse_kwin_buttons(left: &str, right: &str) -> Option<ButtonLayout> {
+    let left_buttons = left.chars().filter_map(kwin_button).collect::<Vec<_>>();
+    // The letters are in start to end order, the end side needs the pack_end order
+    let right_buttons = right
+        .chars()
+        .rev()
+        .filter_map(kwin_button)
+        .collect::<Vec<_>>();
+
+    if left_buttons.is_empty() && right_buttons.is_empty() {
+        return None;
+    }
+
+    Some(ButtonLayout {
+        left_buttons,
+        right_buttons,
+        source: LayoutSource::KdeConfig,
+    })
+}
+
+/// Map a kwin button letter, the kwin menu button shows the application icon like
+/// [`ButtonKind::Icon`]
+fn kwin_button(letter: char) -> Option<ButtonKind> {
+    match letter {
+        'X' => Some(ButtonKind::Close),
+        'A' => Some(ButtonKind::Maximize),
+        'I' => Some(ButtonKind::Minimize),
+        'M' => Some(ButtonKind::Icon),
+        'N' => Some(ButtonKind::Menu),
+        '_' => Some(ButtonKind::Spacer),
+        other => {
+            trace!(%other, "unsupported kwin button");
+
+            None
+        }
+    }
+}
+
+/// Parse a gtk button layout such as `icon:minimize,maximize,close`, `None` when it has no
+/// supported button
+fn parse_button_layout(config: &str, source: LayoutSource) -> Option<ButtonLayout> {
+    // Split by left/right side
+    let (left, right) = config.split_once(':')?;
+
+    debug!(left, right, "button layout config");
+
+    // collect_buttons returns the pack_end order, the start side needs the config order
+    let mut left_buttons = collect_buttons(left);
+    left_buttons.reverse();
+
+    let right_buttons = collect_buttons(right);
+
+    if left_buttons.is_empty() && right_buttons.is_empty() {
+        return None;
+    }
+
+    Some(ButtonLayout {
+        left_buttons,
+        right_buttons,
+        source,
+    })
+}
+
+/// Drop the repeated kinds and keep the first ones, the spacers can repeat
+pub fn dedup_buttons(kinds: Vec<ButtonKind>) -> Vec<ButtonKind> {
+    let mut buttons = Vec::with_capacity(kinds.len());
+    for kind in kinds {
+        if kind != ButtonKind::Spacer && buttons.contains(&kind) {
+            warn!(?kind, "repeated button, ignore it");
+
+            continue;
+        }
+
+        buttons.push(kind);
+    }
+
+    buttons
 }
 
 fn collect_buttons(config: &str) -> Vec<ButtonKind> {
     let mut buttons = config
         .split(',')
-        .take(3)
+        .map(str::trim)
+        .filter(|kind| !kind.is_empty())
         .filter_map(|kind| match kind {
             "close" => Some(ButtonKind::Close),
             "maximize" => Some(ButtonKind::Maximize),
             "minimize" => Some(ButtonKind::Minimize),
+            "menu" | "appmenu" => Some(ButtonKind::Menu),
+            "spacer" => Some(ButtonKind::Spacer),
+            "icon" => Some(ButtonKind::Icon),
             other => {
                 warn!(other, "unsupported button");
 
@@ -98,3 +347,138 @@ fn collect_buttons(config: &str) -> Vec<ButtonKind> {
 
     buttons
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn collect_buttons_reverses_for_pack_end() {
+        assert_eq!(
+            collect_buttons("minimize,maximize,close"),
+            [
+                ButtonKind::Close,
+                ButtonKind::Maximize,
+                ButtonKind::Minimize
+            ]
+        );
+        assert_eq!(
+            collect_buttons("close,maximize,minimize"),
+            [
+                ButtonKind::Minimize,
+                ButtonKind::Maximize,
+                ButtonKind::Close
+            ]
+        );
+    }
+
+    #[test]
+    fn collect_buttons_keeps_more_than_three() {
+        assert_eq!(
+            collect_buttons("icon,menu,minimize,maximize,close"),
+            [
+                ButtonKind::Close,
+                ButtonKind::Maximize,
+                ButtonKind::Minimize,
+                ButtonKind::Menu,
+                ButtonKind::Icon,
+            ]
+        );
+    }
+
+    #[test]
+    fn collect_buttons_skips_unsupported() {
+        assert_eq!(
+            collect_buttons("close,foo,minimize,bar"),
+            [ButtonKind::Minimize, ButtonKind::Close]
+        );
+    }
+
+    #[test]
+    fn collect_buttons_empty() {
+        assert!(collect_buttons("").is_empty());
+        assert!(collect_buttons(",,").is_empty());
+    }
+
+    #[test]
+    fn parse_button_layout_both_sides() {
+        let layout =
+            parse_button_layout("icon:minimize,maximize,close", LayoutSource::EnvOverride).unwrap();
+
+        assert_eq!(layout.left_buttons, [ButtonKind::Icon]);
+        assert_eq!(
+            layout.right_buttons,
+            [
+                ButtonKind::Close,
+                ButtonKind::Maximize,
+                ButtonKind::Minimize
+            ]
+        );
+        assert_eq!(layout.source, LayoutSource::EnvOverride);
+    }
+
+    #[test]
+    fn parse_button_layout_rejects_unparseable() {
+        assert!(parse_button_layout("close,minimize", LayoutSource::EnvOverride).is_none());
+        assert!(parse_button_layout("foo:bar", LayoutSource::EnvOverride).is_none());
+        assert!(parse_button_layout(":", LayoutSource::EnvOverride).is_none());
+    }
+
+    #[test]
+    fn dedup_buttons_keeps_first_and_spacers() {
+        assert_eq!(
+            dedup_buttons(vec![
+                ButtonKind::Close,
+                ButtonKind::Spacer,
+                ButtonKind::Minimize,
+                ButtonKind::Spacer,
+                ButtonKind::Close,
+            ]),
+            [
+                ButtonKind::Close,
+                ButtonKind::Spacer,
+                ButtonKind::Minimize,
+                ButtonKind::Spacer,
+            ]
+        );
+    }
+
+    #[test]
+    fn read_kwin_buttons_from_decoration_group() {
+        let kwinrc = "[Windows]\nButtonsOnLeft=X\n\n[org.kde.kdecoration2]\nButtonsOnLeft=XIA\n";
+
+        assert_eq!(
+            read_kwin_buttons(kwinrc),
+            Some(("XIA".to_string(), KWIN_DEFAULT_RIGHT.to_string()))
+        );
+        assert_eq!(
+            read_kwin_buttons("[org.kde.kdecoration2]\nTheme=Breeze\n"),
+            None
+        );
+    }
+
+    #[test]
+    fn parse_kwin_buttons_default() {
+        let layout = parse_kwin_buttons(KWIN_DEFAULT_LEFT, KWIN_DEFAULT_RIGHT).unwrap();
+
+        assert_eq!(layout.left_buttons, [ButtonKind::Icon]);
+        assert_eq!(
+            layout.right_buttons,
+            [
+                ButtonKind::Close,
+                ButtonKind::Maximize,
+                ButtonKind::Minimize
+            ]
+        );
+        assert_eq!(layout.source, LayoutSource::KdeConfig);
+        assert!(parse_kwin_buttons("SH", "FB").is_none());
+    }
+
+    #[test]
+    fn collect_buttons_trims_whitespace() {
+        assert_eq!(
+            collect_buttons(" close , minimize "),
+            [ButtonKind::Minimize, ButtonKind::Close]
+        );
+    }
+}
@@ -1,14 +1,28 @@
+use std::env;
+use std::fmt::{self, Debug, Formatter};
+use std::mem;
 use std::num::NonZeroU32;
-use std::sync::{Arc, Once};
+use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
+#[cfg(feature = "gtk")]
+use std::sync::{Mutex, Once, OnceLock};
 use std::time::Duration;
-use std::{array, mem};
 
-use gtk::cairo::{Context, Format, ImageSurface};
-use gtk::prelude::{
-    ContainerExt, GtkWindowExt, HeaderBarExt, ImageExt, StyleContextExt, WidgetExt,
+#[cfg(feature = "gtk")]
+use gtk::{
+    atk::prelude::AtkObjectExt,
+    cairo::{Context, Format, ImageSurface},
+    gdk_pixbuf::{InterpType, Pixbuf},
+    glib::IsA,
+    pango::{AttrFontDesc, AttrList, EllipsizeMode, FontDescription},
+    prelude::{
+        Cast, ContainerExt, CssProviderExt, GtkSettingsExt, GtkWindowExt, HeaderBarExt, ImageExt,
+        LabelExt, StyleContextExt, WidgetExt, WidgetExtManual,
+    },
+    Align, Box as GtkBox, Button, Container, CssProvider, HeaderBar, IconSize, Image, Label,
+    OffscreenWindow, Orientation, StateFlags, TextDirection, Widget,
 };
-use gtk::{Align, Button, HeaderBar, IconSize, Image, OffscreenWindow, StateFlags};
-use smithay_client_toolkit::compositor::SurfaceData;
+use smithay_client_toolkit::compositor::{Region, SurfaceData};
 use smithay_client_toolkit::reexports::client::backend::ObjectId;
 use smithay_client_toolkit::reexports::client::protocol::wl_shm;
 use smithay_client_toolkit::reexports::client::protocol::wl_subsurface::WlSubsurface;
@@ -17,26 +31,219 @@ use smithay_client_toolkit::reexports::client::{Dispatch, Proxy, QueueHandle};
 use smithay_client_toolkit::reexports::csd_frame::{
     CursorIcon, DecorationsFrame, FrameAction, FrameClick, WindowManagerCapabilities, WindowState,
 };
+use smithay_client_toolkit::reexports::protocols::wp::cursor_shape::v1::client::wp_cursor_shape_device_v1::WpCursorShapeDeviceV1;
+use smithay_client_toolkit::reexports::protocols::wp::viewporter::client::wp_viewport::WpViewport;
+use smithay_client_toolkit::seat::pointer::cursor_shape::cursor_icon_to_shape;
 use smithay_client_toolkit::shell::WaylandSurface;
-use smithay_client_toolkit::shm::slot::SlotPool;
-use smithay_client_toolkit::shm::Shm;
+use smithay_client_toolkit::shm::slot::{Buffer, SlotPool};
+use smithay_client_toolkit::shm::{ProvidesBoundShm, Shm};
 use smithay_client_toolkit::subcompositor::{SubcompositorState, SubsurfaceData};
-use tiny_skia::{Color, PixmapMut, Rect, Transform};
-
-use crate::layout::get_button_layout;
-use crate::pointer::{ButtonKind, Location, MouseState};
-use crate::shadow::{Shadow, ShadowPart, ShadowSurface, Theme as ShadowTheme};
-
+use tiny_skia::{Color, Pixmap, PixmapMut, Rect, Transform};
+use tracing::{debug, error, trace, warn};
+
+pub use crate::builder::GtkFrameBuilder;
+use crate::color_scheme::cached_color_scheme;
+pub use crate::color_scheme::ColorScheme;
+pub use crate::config::{ButtonLabels, GtkFrameConfig, TitlebarAction};
+pub use crate::handle::GtkFrameHandle;
+use crate::layout::{cached_button_layout, dedup_buttons};
+pub use crate::layout::{clear_button_layout_cache, LayoutSource};
+pub use crate::pointer::ButtonKind;
+use crate::pointer::{Location, MouseState, TouchPoint};
+pub use crate::renderer::{ButtonRect, HeaderContext, HeaderRenderer};
+use crate::shadow::{Shadow, ShadowContent, ShadowPart, ShadowSurface};
+pub use crate::shadow::{ShadowStyle, Theme as ShadowTheme};
+pub use crate::skia::SkiaHeaderRenderer;
+pub use crate::style::HeaderTheme;
+#[cfg(feature = "gtk")]
+use crate::style::{add_provider_recursive, apply_widget_css, css_color, dark_theme_provider};
+
+mod builder;
+mod color_scheme;
+mod config;
+mod handle;
 mod layout;
 mod pointer;
+mod portal;
+mod renderer;
 mod shadow;
+mod skia;
+mod style;
 
+/// Set to `1` to turn the decorations off, see [`GtkFrameBuilder::decorations`]
+const NO_DECORATIONS_ENV: &str = "SCTK_GTK_NO_DECORATIONS";
+
+/// The fallback header bar height when gtk can't measure it, and the height without gtk
 const HEADER_SIZE: u32 = 50;
 const BORDER_SIZE: u32 = 44;
 const VISIBLE_BORDER_SIZE: u32 = 1;
+/// The header bar height of the compact preset, see [`GtkFrameBuilder::compact`]
+const COMPACT_HEADER_SIZE: u32 = 32;
+/// The titlebar button size of the compact preset, the 16px icon with a 2px padding around it
+#[cfg(feature = "gtk")]
+const COMPACT_BUTTON_SIZE: u32 = 20;
+// The compact buttons must not be clipped by the compact header bar.
+#[cfg(feature = "gtk")]
+const _: () =
+    assert!(COMPACT_BUTTON_SIZE >= 16 + 2 * 2 && COMPACT_BUTTON_SIZE < COMPACT_HEADER_SIZE);
+/// The width of the gap a spacer adds between buttons
+const SPACER_SIZE: i32 = 12;
+/// The same size gtk uses for the window icon in the titlebar
+#[cfg(feature = "gtk")]
+const WINDOW_ICON_SIZE: i32 = 20;
+/// The default size of the resize grab area along the edges at scale 1
+const RESIZE_EDGE_THRESHOLD: f64 = 5.0;
+/// The width of the resize handle outside the window, the transparent shadow beyond it doesn't
+/// take the input
+const RESIZE_HANDLE_SIZE: u32 = 10;
+/// The pool is shrunk when it is this many times larger than the buffers need
+const POOL_SHRINK_FACTOR: usize = 4;
+/// Pools up to this size are never shrunk
+const MIN_SHRINK_POOL_SIZE: usize = 4 * 1024 * 1024;
+/// The largest buffer width or height, it is the cairo image surface limit
+const MAX_BUFFER_SIZE: u32 = 32767;
+/// The most gtk events handled before drawing, so a busy gtk can't starve the caller
+#[cfg(feature = "gtk")]
+const MAX_GTK_ITERATIONS: u32 = 100;
+
+/// gtk can only init once, the result is kept for the later frames
+#[cfg(feature = "gtk")]
+static GTK_INIT: OnceLock<Result<(), String>> = OnceLock::new();
+
+/// The gtk caches filled by the preload are shared by all the frames
+#[cfg(feature = "gtk")]
+static GTK_PRELOAD_ONCE: Once = Once::new();
+
+/// The natural header bar height of the loaded gtk theme, `None` when it must be measured again
+#[cfg(feature = "gtk")]
+static NATURAL_HEADER_SIZE: Mutex<Option<u32>> = Mutex::new(None);
+
+/// Measure the natural header bar height again once the gtk theme changes
+#[cfg(feature = "gtk")]
+static GTK_THEME_WATCH_ONCE: Once = Once::new();
+
+/// Horizontal alignment of the title in the header bar
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum TitleAlign {
+    /// Center the title, this is what gtk does
+    #[default]
+    Center,
+    /// Align the title to the start of the space left by the buttons
+    Start,
+    /// Align the title to the end of the space left by the buttons
+    End,
+}
+
+/// How much space the decorations take around the window, see [`GtkFrame::frame_metrics`]
+///
+/// The sizes are in logical pixels.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct FrameMetrics {
+    /// The header bar height above the window, 0 when the header bar isn't shown
+    pub header_height: u32,
+    /// The border thickness around the window, 0 when the borders aren't shown
+    pub border_size: u32,
+    /// Whether the shadow is drawn in the borders
+    pub shadow: bool,
+    /// The scale the decorations are rendered at
+    pub scale: f64,
+}
+
+/// Boxed callback which can be stored in the [`Debug`] [`GtkFrame`]
+struct Callback<F: ?Sized>(Box<F>);
+
+impl<F: ?Sized> Debug for Callback<F> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str("Callback")
+    }
+}
+
+impl<F: ?Sized> Deref for Callback<F> {
+    type Target = F;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<F: ?Sized> DerefMut for Callback<F> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+/// The gtk widgets of the header bar, they are reused across draws until the content changes
+#[cfg(feature = "gtk")]
+#[derive(Debug)]
+struct HeaderWidgets {
+    offscreen_window: OffscreenWindow,
+
+    /// The buttons of both sides, `None` for the buttons which are not packed
+    buttons: Vec<Option<Widget>>,
+}
+
+#[cfg(feature = "gtk")]
+impl Drop for HeaderWidgets {
+    fn drop(&mut self) {
+        // gtk keeps the toplevel windows alive until they are destroyed.
+        unsafe { self.offscreen_window.destroy() }
+    }
+}
+
+/// The bound `wl_shm`, [`Shm`] is only borrowed by the builder
+#[derive(Debug)]
+struct BoundShm(wl_shm::WlShm);
+
+impl ProvidesBoundShm for BoundShm {
+    fn shm(&self) -> &wl_shm::WlShm {
+        &self.0
+    }
+}
+
+/// The size and format a buffer is allocated with
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+struct BufferSpec {
+    width: u32,
+    height: u32,
+    format: wl_shm::Format,
+}
+
+/// The last buffer of a surface, it is reused while the size doesn't change
+#[derive(Debug)]
+struct CachedBuffer<B = Buffer> {
+    buffer: B,
+    spec: BufferSpec,
+}
+
+/// The buffer allocations of [`cached_buffer`], it is the [`SlotPool`] outside of the tests
+trait BufferPool {
+    type Buffer;
+
+    fn create_buffer(&mut self, spec: BufferSpec, stride: usize) -> anyhow::Result<Self::Buffer>;
 
-/// gtk can only init once
-static GTK_INIT_ONCE: Once = Once::new();
+    /// The canvas of the buffer, `None` while the compositor holds it
+    fn canvas(&mut self, buffer: &Self::Buffer) -> Option<&mut [u8]>;
+}
+
+impl BufferPool for SlotPool {
+    type Buffer = Buffer;
+
+    fn create_buffer(&mut self, spec: BufferSpec, stride: usize) -> anyhow::Result<Buffer> {
+        let (buffer, _) = SlotPool::create_buffer(
+            self,
+            spec.width as _,
+            spec.height as _,
+            stride as _,
+            spec.format,
+        )?;
+
+        Ok(buffer)
+    }
+
+    fn canvas(&mut self, buffer: &Buffer) -> Option<&mut [u8]> {
+        SlotPool::canvas(self, buffer)
+    }
+}
 
 #[derive(Debug)]
 struct ButtonState {
@@ -45,60 +252,201 @@ struct ButtonState {
     width: u32,
     height: u32,
     button_kind: ButtonKind,
+
+    /// The button is held down by the pointer or a touch point
+    pressed: bool,
+}
+
+impl ButtonState {
+    fn new(button_kind: ButtonKind) -> Self {
+        Self {
+            x: 0,
+            y: 0,
+            width: 0,
+            height: 0,
+            button_kind,
+            pressed: false,
+        }
+    }
 }
 
 /// Gtk style wayland frame
 ///
 /// [`GtkFrame`] will create a gtk style frame and add gtk style shadow around the window
+///
+/// The frame keeps the gtk widgets of the header bar between draws, so it must stay on the thread
+/// gtk was inited on. Without the `gtk` feature the header bar is drawn by
+/// [`SkiaHeaderRenderer`] instead.
 #[derive(Debug)]
 pub struct GtkFrame {
     /// The drawable decorations, `None` when hidden.
     hidden: bool,
 
+    /// The decorations are turned off, so the frame stays hidden and the server can decorate
+    decorations_disabled: bool,
+
     /// Memory pool to allocate the buffers for the decorations.
     pool: SlotPool,
 
+    /// The shm global, to recreate a smaller pool
+    shm: BoundShm,
+
     /// Whether the frame should be redrawn.
     dirty: bool,
 
-    /// Whether the drawing should be synced with the main surface.
-    should_sync: bool,
+    /// The button rects in the header bar buffer whose hover or pressed state changed, only these
+    /// are damaged when the frame is not dirty otherwise
+    button_damage: Vec<(i32, i32, i32, i32)>,
+
+    /// Whether the header bar drawing should be synced with the main surface.
+    header_should_sync: bool,
+
+    /// Whether the shadow drawing should be synced with the main surface.
+    shadow_should_sync: bool,
+
+    /// Whether the shadow should be redrawn while the header bar is unchanged
+    shadow_dirty: bool,
+
+    /// The resize edge under the pointer, its visible border is highlighted
+    resize_edge: Option<Location>,
 
     /// Scale factor used for the surface.
-    scale_factor: u32,
+    scale_factor: f64,
+
+    /// The scales of the outputs the window is on, one per entered output
+    output_scales: Vec<i32>,
+
+    /// The preferred scale from `wp_fractional_scale_v1`, it is used instead of the integer scale
+    /// factor when the viewports are available
+    fractional_scale: Option<f64>,
 
     /// Whether the frame is resizable.
     resizable: bool,
 
+    /// Sizes, timing and theme of the frame
+    config: GtkFrameConfig,
+
+    /// The header bar height, it is the config override or the natural gtk height
+    header_size: u32,
+
     /// Window width
     width: Option<NonZeroU32>,
 
     /// Window height
     height: Option<NonZeroU32>,
 
-    /// Indicates the frame buttons at the end or start
-    buttons_at_end: bool,
+    /// Buttons state at the start of frame, in `pack_start` order
+    left_buttons: Vec<ButtonState>,
+
+    /// Buttons state at the end of frame, in `pack_end` order
+    right_buttons: Vec<ButtonState>,
 
-    /// Buttons state
-    buttons: Vec<ButtonState>,
+    /// Where the button layout comes from
+    button_layout_source: LayoutSource,
 
+    /// The window state, the activated bit is the last one of the compositor or `set_active`
     state: WindowState,
+    /// The activated bit of the last state from the compositor
+    reported_active: bool,
     wm_capabilities: WindowManagerCapabilities,
 
     /// Mouse state
     mouse: MouseState,
 
+    /// The cached header bar widgets, `None` when they must be rebuilt
+    #[cfg(feature = "gtk")]
+    header_widgets: Option<HeaderWidgets>,
+
+    /// The minimum width in logical pixels of the last laid out header bar
+    min_frame_width: u32,
+
+    /// The button drawn with the hover state
+    hovered_button: Option<ButtonKind>,
+
     /// Frame title
     title: String,
 
+    /// Prefix prepended to the rendered title, such as a modified or busy indicator
+    title_prefix: Option<String>,
+
+    /// Subtitle shown below the title, such as a document path
+    subtitle: Option<String>,
+
+    /// Font of the title, `None` uses the gtk theme
+    #[cfg(feature = "gtk")]
+    title_font: Option<FontDescription>,
+
+    /// Application icon shown opposite the buttons
+    #[cfg(feature = "gtk")]
+    window_icon: Option<Pixbuf>,
+
+    /// Colors of the header bar
+    header_theme: HeaderTheme,
+
+    /// Embedder provided css for the header bar widgets
+    #[cfg(feature = "gtk")]
+    custom_css: Option<CssProvider>,
+
+    /// Draws the header bar instead of gtk, `None` draws it with gtk, or with
+    /// [`SkiaHeaderRenderer`] without the `gtk` feature
+    header_renderer: Option<Callback<dyn HeaderRenderer>>,
+
+    /// The cursor shape device and the serial of the last pointer enter, the frame sets the
+    /// cursor itself when it is set
+    cursor_shape_device: Option<(WpCursorShapeDeviceV1, u32)>,
+
+    /// The shape last set on the cursor shape device
+    cursor_shape: Option<CursorIcon>,
+
+    /// The last error of [`DecorationsFrame::draw`], kept until the app takes it
+    last_error: Option<anyhow::Error>,
+
+    /// Text direction of the header bar, the buttons and the title mirror in right to left
+    #[cfg(feature = "gtk")]
+    direction: TextDirection,
+
+    /// The system color scheme the frame is drawn with
+    color_scheme: ColorScheme,
+
+    /// How the title is ellipsized when it doesn't fit the header bar
+    #[cfg(feature = "gtk")]
+    title_ellipsize: EllipsizeMode,
+
+    /// Horizontal alignment of the title
+    #[cfg(feature = "gtk")]
+    title_align: TitleAlign,
+
+    /// Minimum horizontal gap between the title and the buttons
+    #[cfg(feature = "gtk")]
+    title_button_gap: u32,
+
+    /// Embedder provided gtk main loop pump, `None` iterates the gtk main loop directly
+    #[cfg(feature = "gtk")]
+    gtk_pump: Option<Callback<dyn FnMut()>>,
+
+    /// Embedder provided hook to veto the button actions
+    on_button_activated: Option<Callback<dyn FnMut(ButtonKind) -> bool>>,
+
+    /// Embedder provided hook to add widgets to the header bar
+    #[cfg(feature = "gtk")]
+    header_bar_hook: Option<Callback<dyn FnMut(&HeaderBar)>>,
+
     /// Frame wayland surface
     header_bar_surface: WlSurface,
     header_bar_subsurface: WlSubsurface,
+    /// Maps the buffer to the logical size, only when the compositor has `wp_viewporter`
+    header_bar_viewport: Option<WpViewport>,
+    header_bar_buffer: Option<CachedBuffer>,
+    /// A region covering everything, it is the opaque region of an opaque header bar
+    opaque_region: Option<Region>,
+
+    /// A scratch region for the input region of the shadow surfaces
+    input_region: Option<Region>,
 
     /// Window shadow
     shadow: Shadow,
     shadow_surfaces: [ShadowSurface; 4],
-    shadow_theme: ShadowTheme,
+    shadow_buffers: [Option<CachedBuffer>; 4],
 }
 
 impl DecorationsFrame for GtkFrame {
@@ -111,18 +459,31 @@ impl DecorationsFrame for GtkFrame {
         let action = match click {
             FrameClick::Normal => self.mouse.click(
                 timestamp,
+                self.config.double_click_duration,
                 pressed,
                 self.resizable,
                 &self.state,
                 &self.wm_capabilities,
             ),
-            FrameClick::Alternate => self.mouse.alternate_click(pressed, &self.wm_capabilities),
-            _ => None,
+            FrameClick::Alternate => self.mouse.secondary_click(
+                self.config.alternate_click_action,
+                pressed,
+                &self.wm_capabilities,
+            ),
+            other => {
+                trace!(?other, pressed, "unhandled frame click");
+
+                None
+            }
         };
 
+        if matches!(click, FrameClick::Normal) {
+            self.set_pressed_button(pressed, self.mouse.location);
+        }
+
         self.update_dirty_by_button_cursor_pos();
 
-        action
+        self.button_activated(self.mouse.location, action)
     }
 
     fn click_point_moved(
@@ -144,36 +505,93 @@ impl DecorationsFrame for GtkFrame {
             .mouse
             .moved(mouse_location, x, y, self.resizable, self.state);
 
+        let resize_edge = (self.resizable
+            && !self.state.contains(WindowState::MAXIMIZED)
+            && is_resize_edge(mouse_location))
+        .then_some(mouse_location);
+        self.set_resize_edge(resize_edge);
+
         self.update_dirty_by_button_cursor_pos();
 
-        if mouse_location == Location::None {
+        if !self.mouse.in_frame() {
+            // The app sets its own cursor over the client area.
+            self.cursor_shape = None;
+
             return None;
         }
 
+        if let Some((device, serial)) = &self.cursor_shape_device {
+            if self.cursor_shape != Some(cursor_icon) {
+                device.set_shape(*serial, cursor_icon_to_shape(cursor_icon));
+                self.cursor_shape = Some(cursor_icon);
+            }
+        }
+
         Some(cursor_icon)
     }
 
     fn click_point_left(&mut self) {
-        self.mouse.left()
+        self.mouse.left();
+        self.set_resize_edge(None);
+        self.cursor_shape = None;
+        self.update_dirty_by_button_cursor_pos();
+
+        // A button doesn't stay pressed when the pointer leaves, like gtk.
+        if self.mouse.touch.is_none() {
+            self.set_pressed_button(false, Location::None);
+        }
     }
 
-    fn update_state(&mut self, state: WindowState) {
+    fn update_state(&mut self, mut state: WindowState) {
+        // The compositor didn't change the focus, keep the one set by `set_active`.
+        let reported_active = state.contains(WindowState::ACTIVATED);
+        if reported_active == self.reported_active {
+            state.set(
+                WindowState::ACTIVATED,
+                self.state.contains(WindowState::ACTIVATED),
+            );
+        }
+        self.reported_active = reported_active;
+
         let difference = self.state.symmetric_difference(state);
         self.state = state;
-        self.dirty |= difference.intersects(
+
+        if difference.contains(WindowState::SUSPENDED) {
+            if state.contains(WindowState::SUSPENDED) {
+                debug!("frame suspended, skip drawing until it resumes");
+            } else {
+                // The changes while suspended were not drawn, so redraw everything.
+                self.invalidate_header();
+                self.header_should_sync = true;
+                self.shadow_should_sync = true;
+
+                return;
+            }
+        }
+
+        if difference.intersects(
             WindowState::ACTIVATED
                 | WindowState::FULLSCREEN
                 | WindowState::MAXIMIZED
                 | WindowState::TILED,
-        );
+        ) {
+            self.invalidate_header();
+        }
     }
 
     fn update_wm_capabilities(&mut self, wm_capabilities: WindowManagerCapabilities) {
-        self.dirty |= self.wm_capabilities != wm_capabilities;
-        self.wm_capabilities = wm_capabilities;
+        if self.wm_capabilities != wm_capabilities {
+            self.wm_capabilities = wm_capabilities;
+            self.invalidate_header();
+        }
     }
 
     fn resize(&mut self, width: NonZeroU32, height: NonZeroU32) {
+        // The header bar only follows the width, while the right and bottom shadows are moved
+        // by any size change, so they must be committed together with the main surface.
+        self.header_should_sync |= self.width != Some(width);
+        self.shadow_should_sync |= self.width != Some(width) || self.height != Some(height);
+
         self.width = Some(width);
         self.height = Some(height);
 
@@ -182,35 +600,36 @@ impl DecorationsFrame for GtkFrame {
 
         // top
         let shadow_surface = &mut self.shadow_surfaces[ShadowPart::Top.index()];
-        shadow_surface.width = width + 2 * BORDER_SIZE;
+        shadow_surface.width = width.saturating_add(2 * self.config.border_size);
 
         // bottom
         let shadow_surface = &mut self.shadow_surfaces[ShadowPart::Bottom.index()];
-        shadow_surface.width = width + 2 * BORDER_SIZE;
+        shadow_surface.width = width.saturating_add(2 * self.config.border_size);
         shadow_surface.y = height as _;
 
         // left
         let shadow_surface = &mut self.shadow_surfaces[ShadowPart::Left.index()];
-        shadow_surface.height = height + HEADER_SIZE;
+        shadow_surface.height = height.saturating_add(self.header_size);
 
         // right
         let shadow_surface = &mut self.shadow_surfaces[ShadowPart::Right.index()];
-        shadow_surface.height = height + HEADER_SIZE;
+        shadow_surface.height = height.saturating_add(self.header_size);
         shadow_surface.x = width as _;
     }
 
     fn set_scaling_factor(&mut self, scale_factor: f64) {
         // NOTE: Clamp it just in case to some ok-ish range.
-        self.scale_factor = scale_factor.clamp(0.1, 64.).ceil() as u32;
+        self.scale_factor = scale_factor.clamp(0.1, 64.);
         self.dirty = true;
-        self.should_sync = true;
+        self.header_should_sync = true;
+        self.shadow_should_sync = true;
     }
 
     fn location(&self) -> (i32, i32) {
         if self.hidden || self.state.contains(WindowState::FULLSCREEN) {
             (0, 0)
         } else {
-            (0, -(HEADER_SIZE as i32))
+            (0, -(self.header_size as i32))
         }
     }
 
@@ -224,7 +643,7 @@ impl DecorationsFrame for GtkFrame {
         } else {
             (
                 Some(width),
-                NonZeroU32::new(height.get().saturating_sub(HEADER_SIZE)),
+                NonZeroU32::new(height.get().saturating_sub(self.header_size)),
             )
         }
     }
@@ -233,22 +652,46 @@ impl DecorationsFrame for GtkFrame {
         if self.hidden || self.state.contains(WindowState::FULLSCREEN) {
             (width, height)
         } else {
-            (width, height + HEADER_SIZE)
+            (width, height.saturating_add(self.header_size))
         }
     }
 
     fn is_dirty(&self) -> bool {
+        // A suspended window is not visible, it is redrawn once it resumes.
+        if self.hidden || self.state.contains(WindowState::SUSPENDED) {
+            return false;
+        }
+
         self.dirty
+            || self.shadow_dirty
+            || !self.button_damage.is_empty()
+            || self.color_scheme != cached_color_scheme()
     }
 
     fn set_hidden(&mut self, hidden: bool) {
+        if !hidden && self.decorations_disabled {
+            debug!("decorations are disabled, keep the frame hidden");
+
+            return;
+        }
+
         self.hidden = hidden;
         if hidden {
             self.dirty = false;
+            self.button_damage.clear();
+            #[cfg(feature = "gtk")]
+            self.header_widgets = None;
             let _ = self.pool.resize(1);
+
+            // The surfaces keep their last buffers until a null buffer is attached.
+            self.header_bar_buffer = None;
+            self.header_bar_surface.attach(None, 0, 0);
+            self.header_bar_surface.commit();
+            self.detach_shadow();
         } else {
             self.dirty = true;
-            self.should_sync = true;
+            self.header_should_sync = true;
+            self.shadow_should_sync = true;
         }
     }
 
@@ -261,24 +704,56 @@ impl DecorationsFrame for GtkFrame {
     }
 
     fn draw(&mut self) -> bool {
-        let should_sync = self.draw_head_bar().unwrap_or(false);
-        let _ = self.draw_shadow(should_sync);
+        if self.hidden || self.state.contains(WindowState::SUSPENDED) {
+            return false;
+        }
+
+        // The color scheme is refreshed in background when the portal signals a change.
+        let color_scheme = cached_color_scheme();
+        if self.color_scheme != color_scheme {
+            self.color_scheme = color_scheme;
+            self.invalidate_header();
+        }
+
+        self.shrink_pool();
 
-        should_sync
+        // Only the shadow changed, such as the highlighted resize edge, the header bar keeps its
+        // buffer.
+        let shadow_only = self.shadow_dirty
+            && !self.dirty
+            && self.button_damage.is_empty()
+            && !self.header_should_sync;
+
+        let header_should_sync = !shadow_only
+            && self.draw_head_bar().unwrap_or_else(|err| {
+                error!(%err, "draw header bar failed");
+                self.last_error = Some(err.context("draw header bar failed"));
+
+                false
+            });
+        let shadow_should_sync = self.draw_shadow().unwrap_or_else(|err| {
+            error!(%err, "draw shadow failed");
+            self.last_error = Some(err.context("draw shadow failed"));
+
+            false
+        });
+
+        header_should_sync || shadow_should_sync
     }
 
     fn set_title(&mut self, title: impl Into<String>) {
         self.title = title.into();
-        self.dirty = true;
+        self.invalidate_header();
     }
 }
 
 impl GtkFrame {
     /// Create a new [`GtkFrame`], if `gtk_init` is true, it will init gtk at most once
     ///
-    /// # Panic
+    /// # Errors
     ///
-    /// if gtk init failed, will panic
+    /// if gtk init failed, see [`GtkFrameBuilder::build`]
+    #[cfg(feature = "gtk")]
     pub fn new_with_gtk_init<State>(
         base_surface: &impl WaylandSurface,
         shm: &Shm,
@@ -289,73 +764,16 @@ impl GtkFrame {
     where
         State: Dispatch<WlSurface, SurfaceData> + Dispatch<WlSubsurface, SubsurfaceData> + 'static,
     {
-        if gtk_init {
-            GTK_INIT_ONCE
-                .call_once(|| gtk::init().unwrap_or_else(|err| panic!("gtk init failed: {err}")));
-        }
-
-        let (buttons_at_end, buttons) = get_button_layout();
-        let buttons = buttons
-            .into_iter()
-            .map(|kind| ButtonState {
-                x: 0,
-                y: 0,
-                width: 0,
-                height: 0,
-                button_kind: kind,
-            })
-            .collect();
-
-        let (subsurface, surface) =
-            sub_compositor.create_subsurface(base_surface.wl_surface().clone(), &queue_handle);
-
-        subsurface.set_sync();
-
-        let pool = SlotPool::new(1, shm)?;
-
-        let mut shadow_surfaces = array::from_fn(|_| {
-            let (subsurface, surface) =
-                sub_compositor.create_subsurface(base_surface.wl_surface().clone(), &queue_handle);
-
-            ShadowSurface {
-                surface,
-                subsurface,
-                x: 0,
-                y: 0,
-                width: 0,
-                height: 0,
-            }
-        });
-        init_shadow_surfaces_pos(&mut shadow_surfaces);
-
-        Ok(Self {
-            hidden: false,
-            pool,
-            dirty: true,
-            should_sync: true,
-            scale_factor: 1,
-            resizable: true,
-            width: None,
-            height: None,
-            buttons_at_end,
-            buttons,
-            state: WindowState::empty(),
-            wm_capabilities: WindowManagerCapabilities::all(),
-            mouse: Default::default(),
-            title: String::new(),
-            header_bar_surface: surface,
-            header_bar_subsurface: subsurface,
-            shadow: Default::default(),
-            shadow_surfaces,
-            shadow_theme: ShadowTheme::auto(),
-        })
+        GtkFrameBuilder::new(base_surface, shm, sub_compositor, queue_handle)
+            .gtk_init(gtk_init)
+            .build()
     }
 
     /// Create a new [`GtkFrame`]
     ///
-    /// # Panic
+    /// # Errors
     ///
-    /// if gtk init failed, will panic
+    /// if gtk init failed, see [`GtkFrameBuilder::build`]
     pub fn new<State>(
         base_surface: &impl WaylandSurface,
         shm: &Shm,
@@ -365,128 +783,908 @@ impl GtkFrame {
     where
         State: Dispatch<WlSurface, SurfaceData> + Dispatch<WlSubsurface, SubsurfaceData> + 'static,
     {
-        Self::new_with_gtk_init(base_surface, shm, sub_compositor, queue_handle, true)
+        GtkFrameBuilder::new(base_surface, shm, sub_compositor, queue_handle).build()
     }
 
-    fn get_cursor_area(&mut self, surface_id: &ObjectId) -> CursorArea {
-        if self.header_bar_surface.id() == *surface_id {
-            CursorArea::Frame
-        } else {
-            match self
-                .shadow_surfaces
-                .iter()
-                .enumerate()
-                .find_map(|(index, shadow_surface)| {
-                    (shadow_surface.surface.id() == *surface_id).then_some(index)
-                }) {
-                None => CursorArea::Window,
-                Some(index) => {
-                    if index == ShadowPart::Top.index() {
-                        CursorArea::TopShadow
-                    } else if index == ShadowPart::Left.index() {
-                        CursorArea::LeftShadow
-                    } else if index == ShadowPart::Right.index() {
-                        CursorArea::RightShadow
-                    } else if index == ShadowPart::Bottom.index() {
-                        CursorArea::BottomShadow
-                    } else {
-                        unreachable!()
-                    }
-                }
-            }
-        }
+    /// Create a new [`GtkFrame`] with the `config`
+    ///
+    /// # Errors
+    ///
+    /// if gtk init failed, see [`GtkFrameBuilder::build`]
+    pub fn with_config<State>(
+        base_surface: &impl WaylandSurface,
+        shm: &Shm,
+        sub_compositor: Arc<SubcompositorState>,
+        queue_handle: QueueHandle<State>,
+        config: GtkFrameConfig,
+    ) -> anyhow::Result<Self>
+    where
+        State: Dispatch<WlSurface, SurfaceData> + Dispatch<WlSubsurface, SubsurfaceData> + 'static,
+    {
+        GtkFrameBuilder::new(base_surface, shm, sub_compositor, queue_handle)
+            .config(config)
+            .build()
     }
 
-    fn update_dirty_by_button_cursor_pos(&mut self) {
-        if !self.mouse.in_frame() {
-            return;
-        }
+    /// Create a [`GtkFrameBuilder`] to customize the [`GtkFrame`]
+    pub fn builder<'a, State>(
+        base_surface: &impl WaylandSurface,
+        shm: &'a Shm,
+        sub_compositor: Arc<SubcompositorState>,
+        queue_handle: QueueHandle<State>,
+    ) -> GtkFrameBuilder<'a, State> {
+        GtkFrameBuilder::new(base_surface, shm, sub_compositor, queue_handle)
+    }
 
-        if let Location::Button(kind) = self.mouse.location {
-            for state in &self.buttons {
-                if state.button_kind == kind {
-                    self.dirty = true;
-                    return;
-                }
-            }
-        }
+    /// The title set by [`DecorationsFrame::set_title`], without the prefix
+    pub fn title(&self) -> &str {
+        &self.title
     }
 
-    fn mouse_location(
-        &self,
-        x: f64,
-        y: f64,
-        width: u32,
-        height: u32,
-        cursor_area: CursorArea,
-    ) -> Location {
-        match cursor_area {
-            CursorArea::Frame => {
-                if x <= 5.0 && y <= 5.0 {
-                    Location::TopLeft
-                } else if x >= (width - 5) as _ && y <= 5.0 {
-                    Location::TopRight
-                } else if x > 5.0 && x < (width - 5) as _ && y < 5.0 {
-                    Location::Top
-                } else {
-                    for state in &self.buttons {
-                        if Self::in_button((x, y), state) {
-                            return Location::Button(state.button_kind);
-                        }
-                    }
+    /// The header bar height in logical pixels
+    pub fn header_height(&self) -> u32 {
+        self.header_size
+    }
 
-                    Location::Head
-                }
-            }
+    /// The smallest window width in logical pixels that fits the header bar buttons and the
+    /// ellipsized title, apps can use it as the xdg toplevel minimum width
+    ///
+    /// It is measured from the last laid out header bar, so it is 0 until the first draw, and
+    /// it keeps the last width while the header bar waits for a rebuild, such as after the title
+    /// changes.
+    pub fn min_frame_width(&self) -> u32 {
+        self.min_frame_width
+    }
 
-            CursorArea::TopShadow => {
-                if x <= 5.0 {
-                    Location::TopLeft
-                } else if x >= (width - 5) as _ {
-                    Location::TopRight
-                } else {
-                    Location::Top
-                }
-            }
+    /// The border thickness around the window in logical pixels, it includes the invisible resize
+    /// area, the shadow and the visible border
+    pub fn border_size(&self) -> u32 {
+        self.config.border_size
+    }
 
-            CursorArea::BottomShadow => {
-                if x <= 5.0 {
-                    Location::BottomLeft
-                } else if x >= (width - 5) as _ {
-                    Location::BottomRight
-                } else {
-                    Location::Bottom
-                }
-            }
+    /// The current size of the decorations, it follows the hidden, fullscreen and maximized
+    /// states like [`DecorationsFrame::add_borders`] and [`DecorationsFrame::location`]
+    pub fn frame_metrics(&self) -> FrameMetrics {
+        if self.hidden || self.state.contains(WindowState::FULLSCREEN) {
+            return FrameMetrics {
+                header_height: 0,
+                border_size: 0,
+                shadow: false,
+                scale: self.header_scale(),
+            };
+        }
 
-            CursorArea::LeftShadow => {
-                if y <= 5.0 {
-                    Location::TopLeft
-                } else if y >= (height - 5) as _ {
-                    Location::BottomLeft
-                } else {
-                    Location::Left
-                }
-            }
+        FrameMetrics {
+            header_height: self.header_size,
+            border_size: self.config.border_size,
+            shadow: self.config.shadow_enabled && !self.state.contains(WindowState::MAXIMIZED),
+            scale: self.header_scale(),
+        }
+    }
 
-            CursorArea::RightShadow => {
-                if y <= 5.0 {
-                    Location::TopRight
-                } else if y >= (height - 5) as _ {
-                    Location::BottomRight
-                } else {
-                    Location::Right
-                }
-            }
+    /// Let the frame set the cursor of the decorations with `wp_cursor_shape_v1`, call it with the
+    /// serial of every pointer enter on the decoration surfaces, `None` stops it
+    ///
+    /// [`DecorationsFrame::click_point_moved`] still returns the cursor icon, the app should not
+    /// set the cursor again when it uses this.
+    pub fn set_cursor_shape_device(
+        &mut self,
+        device: Option<WpCursorShapeDeviceV1>,
+        enter_serial: u32,
+    ) {
+        self.cursor_shape_device = device.map(|device| (device, enter_serial));
+        // The compositor resets the cursor on enter.
+        self.cursor_shape = None;
+    }
 
-            CursorArea::Window => Location::None,
+    /// Where a window menu opened by the pointer on the header bar should appear, in the
+    /// coordinates of the window surface, so above the window by the header height
+    ///
+    /// It is the position of [`FrameAction::ShowMenu`], apps with their own menu can reuse it.
+    /// `None` when the pointer is not on the header bar or the header bar isn't shown.
+    pub fn window_menu_position(&self) -> Option<(i32, i32)> {
+        if self.hidden || self.state.contains(WindowState::FULLSCREEN) {
+            return None;
         }
+
+        self.mouse.window_menu_position()
     }
 
-    fn draw_head_bar(&mut self) -> anyhow::Result<bool> {
+    /// Take the last error of [`DecorationsFrame::draw`], such as a failed buffer allocation
+    ///
+    /// `draw` can only return whether the surfaces should be committed, a failed draw leaves the
+    /// decorations undrawn and keeps the error here.
+    pub fn take_last_error(&mut self) -> Option<anyhow::Error> {
+        self.last_error.take()
+    }
+
+    /// Set a prefix prepended to the rendered title, such as `"• "` for a modified document or
+    /// `"… "` for a busy one
+    ///
+    /// The title set by [`DecorationsFrame::set_title`] is kept unchanged.
+    pub fn set_title_prefix(&mut self, prefix: Option<String>) {
+        if self.title_prefix != prefix {
+            self.title_prefix = prefix;
+            self.invalidate_header();
+        }
+    }
+
+    /// Set a subtitle shown below the title, such as a document path or a connection status
+    ///
+    /// The header bar keeps its height, a subtitle taller than the header size is clipped.
+    pub fn set_subtitle(&mut self, subtitle: Option<String>) {
+        if self.subtitle != subtitle {
+            self.subtitle = subtitle;
+            self.invalidate_header();
+        }
+    }
+
+    /// Set the application icon shown in the header bar, at the opposite side of the buttons
+    ///
+    /// Icons larger than the gtk titlebar icon size are scaled down.
+    #[cfg(feature = "gtk")]
+    pub fn set_window_icon(&mut self, icon: Option<Pixbuf>) {
+        self.window_icon = icon;
+        self.invalidate_header();
+    }
+
+    /// Set the background color of the hovered button, `None` uses the gtk theme
+    pub fn set_button_hover_color(&mut self, color: Option<Color>) {
+        if self.header_theme.button_hover != color {
+            self.header_theme.button_hover = color;
+            self.invalidate_header();
+        }
+    }
+
+    /// Set how the title is ellipsized when it doesn't fit the header bar, default is
+    /// [`EllipsizeMode::Middle`]
+    #[cfg(feature = "gtk")]
+    pub fn set_title_ellipsize(&mut self, ellipsize: EllipsizeMode) {
+        if self.title_ellipsize != ellipsize {
+            self.title_ellipsize = ellipsize;
+            self.invalidate_header();
+        }
+    }
+
+    /// Set the font of the title, such as a monospace font for a terminal, `None` uses the gtk
+    /// theme
+    ///
+    /// A title font taller than the header size is clipped, a too wide title is ellipsized.
+    #[cfg(feature = "gtk")]
+    pub fn set_title_font(&mut self, font: Option<FontDescription>) {
+        if self.title_font != font {
+            self.title_font = font;
+            self.invalidate_header();
+        }
+    }
+
+    /// Set the horizontal alignment of the title, default is [`TitleAlign::Center`]
+    #[cfg(feature = "gtk")]
+    pub fn set_title_alignment(&mut self, align: TitleAlign) {
+        if self.title_align != align {
+            self.title_align = align;
+            self.invalidate_header();
+        }
+    }
+
+    /// Re-apply the current button layout of the system, the layout is refreshed in background
+    /// when the XDG Settings Portal signals a change
+    ///
+    /// It also replaces the layout set by [`GtkFrame::set_buttons`].
+    pub fn refresh_button_layout(&mut self) {
+        let button_layout = cached_button_layout();
+
+        self.left_buttons = button_states(button_layout.left_buttons);
+        self.right_buttons = button_states(button_layout.right_buttons);
+        self.button_layout_source = button_layout.source;
+        self.invalidate_header();
+    }
+
+    /// Replace the button layout of the system with `kinds`, from the start to the end, at the
+    /// end of the header bar or at the start
+    ///
+    /// A kind listed twice is only kept the first time, except the spacers.
+    pub fn set_buttons(&mut self, at_end: bool, kinds: Vec<ButtonKind>) {
+        let kinds = dedup_buttons(kinds);
+
+        if at_end {
+            // The end side is in `pack_end` order, which starts from the end.
+            self.left_buttons = Vec::new();
+            self.right_buttons = button_states(kinds.into_iter().rev().collect());
+        } else {
+            self.left_buttons = button_states(kinds);
+            self.right_buttons = Vec::new();
+        }
+
+        self.button_layout_source = LayoutSource::Manual;
+        self.invalidate_header();
+    }
+
+    /// Where the current button layout comes from, useful to debug an unexpected layout
+    pub fn button_layout_source(&self) -> LayoutSource {
+        self.button_layout_source
+    }
+
+    /// Whether the window buttons are at the end of the header bar, on the right in left to right
+    /// layouts
+    ///
+    /// It is the side of the close button, or the side with the buttons when there is no close
+    /// button.
+    pub fn buttons_at_end(&self) -> bool {
+        let has_close = |buttons: &[ButtonState]| {
+            buttons
+                .iter()
+                .any(|state| state.button_kind == ButtonKind::Close)
+        };
+
+        if has_close(&self.right_buttons) {
+            true
+        } else if has_close(&self.left_buttons) {
+            false
+        } else {
+            self.left_buttons.is_empty()
+        }
+    }
+
+    /// The kinds of the header bar buttons from the start to the end, including the spacers and
+    /// the icon
+    pub fn button_kinds(&self) -> impl Iterator<Item = ButtonKind> + '_ {
+        // The end side is in `pack_end` order, which starts from the end.
+        self.left_buttons
+            .iter()
+            .chain(self.right_buttons.iter().rev())
+            .map(|state| state.button_kind)
+    }
+
+    /// Set the minimum horizontal gap in logical pixels between the title and the buttons, so the
+    /// title never touches the buttons on narrow windows
+    #[cfg(feature = "gtk")]
+    pub fn set_title_button_gap(&mut self, gap: u32) {
+        if self.title_button_gap != gap {
+            self.title_button_gap = gap;
+            self.invalidate_header();
+        }
+    }
+
+    /// Let the embedder pump the gtk main loop, instead of calling [`gtk::main_iteration_do`]
+    /// directly
+    ///
+    /// The pump is called before every head bar draw, it must process the pending gtk events so
+    /// the offscreen head bar is laid out. This avoids reentrancy when the application runs its
+    /// own gtk main loop on the same thread.
+    #[cfg(feature = "gtk")]
+    pub fn set_gtk_pump(&mut self, gtk_pump: impl FnMut() + 'static) {
+        self.gtk_pump = Some(Callback(Box::new(gtk_pump)));
+    }
+
+    /// Call `hook` when a titlebar button is released, before its [`FrameAction`] is returned
+    ///
+    /// The action is dropped when the hook returns false, such as to confirm before closing. The
+    /// hook is called synchronously on the thread calling [`DecorationsFrame::on_click`] or
+    /// [`GtkFrame::on_touch_up`], it must not call back into the frame.
+    pub fn set_on_button_activated(&mut self, hook: impl FnMut(ButtonKind) -> bool + 'static) {
+        self.on_button_activated = Some(Callback(Box::new(hook)));
+    }
+
+    /// Let the embedder veto the action of the button at the location
+    fn button_activated(
+        &mut self,
+        location: Location,
+        action: Option<FrameAction>,
+    ) -> Option<FrameAction> {
+        let (Location::Button(button_kind), Some(_)) = (location, &action) else {
+            return action;
+        };
+
+        match &mut self.on_button_activated {
+            Some(hook) if !hook(button_kind) => {
+                debug!(?button_kind, "button action is vetoed");
+
+                None
+            }
+
+            _ => action,
+        }
+    }
+
+    /// Run the `hook` after the default buttons are packed, so it can add its own widgets to the
+    /// header bar, such as a search button
+    ///
+    /// The hook runs every time the header bar is rebuilt, call it again to rebuild the header bar
+    /// after the widgets should change. The widgets are only painted, they can't receive input
+    /// events.
+    #[cfg(feature = "gtk")]
+    pub fn set_header_bar_hook(&mut self, hook: impl FnMut(&HeaderBar) + 'static) {
+        self.header_bar_hook = Some(Callback(Box::new(hook)));
+        self.invalidate_header();
+    }
+
+    /// Set the background color of the header bar, `None` uses the gtk theme
+    ///
+    /// Unless [`GtkFrame::set_title_color`] is used, the title and button icons switch to a light
+    /// or dark color that is readable on the background.
+    pub fn set_header_background(&mut self, color: Option<Color>) {
+        if self.header_theme.background != color {
+            self.header_theme.background = color;
+            self.invalidate_header();
+        }
+    }
+
+    /// Set the color of the title and button icons, `None` picks it automatically
+    pub fn set_title_color(&mut self, color: Option<Color>) {
+        if self.header_theme.foreground != color {
+            self.header_theme.foreground = color;
+            self.invalidate_header();
+        }
+    }
+
+    /// The system color scheme the frame is drawn with, it follows the XDG Settings Portal
+    pub fn color_scheme(&self) -> ColorScheme {
+        self.color_scheme
+    }
+
+    /// Set the text direction of the header bar, default is the gtk default direction of the
+    /// locale
+    ///
+    /// In right to left the buttons and the title mirror like gtk. The resize edges are not
+    /// mirrored, they always follow the physical window edges.
+    #[cfg(feature = "gtk")]
+    pub fn set_direction(&mut self, direction: TextDirection) {
+        let direction = match direction {
+            TextDirection::None => Widget::default_direction(),
+            direction => direction,
+        };
+
+        if self.direction != direction {
+            self.direction = direction;
+            self.invalidate_header();
+        }
+    }
+
+    /// Repaint the whole frame on the next [`DecorationsFrame::draw`], such as after the gtk
+    /// theme or css is changed outside of the frame
+    ///
+    /// The header bar widgets are rebuilt, so they pick up the new style. The frames built after
+    /// it measure the natural header bar height of the new theme.
+    pub fn force_redraw(&mut self) {
+        #[cfg(feature = "gtk")]
+        invalidate_natural_header_size();
+        self.invalidate_header();
+        self.header_should_sync = true;
+        self.shadow_should_sync = true;
+    }
+
+    /// Load custom css for the header bar, such as
+    /// `.titlebutton.close:hover { background-color: red; }`
+    ///
+    /// The css has application priority, so it overrides the gtk theme. It only affects the
+    /// offscreen rendered widgets of the frame, not the application, and replaces the css loaded
+    /// before.
+    #[cfg(feature = "gtk")]
+    pub fn load_css(&mut self, css: &str) -> anyhow::Result<()> {
+        let provider = CssProvider::new();
+        provider.load_from_data(css.as_bytes())?;
+
+        self.custom_css = Some(provider);
+        self.invalidate_header();
+
+        Ok(())
+    }
+
+    /// Set all the colors of the header bar at once, [`HeaderTheme::auto`] follows the gtk theme
+    pub fn set_header_theme(&mut self, header_theme: HeaderTheme) {
+        if self.header_theme != header_theme {
+            self.header_theme = header_theme;
+            self.invalidate_header();
+        }
+    }
+
+    /// The middle click on the frame, [`FrameClick`] has no middle button so the embedder calls
+    /// it directly
+    ///
+    /// The action is [`GtkFrameConfig::middle_click_action`], which does nothing by default.
+    pub fn on_middle_click(&mut self, pressed: bool) -> Option<FrameAction> {
+        let action = self.mouse.secondary_click(
+            self.config.middle_click_action,
+            pressed,
+            &self.wm_capabilities,
+        );
+
+        self.update_dirty_by_button_cursor_pos();
+
+        action
+    }
+
+    /// Set whether the window has the focus, for apps which track the focus apart from the
+    /// toplevel state
+    ///
+    /// It drives the same active look as [`WindowState::ACTIVATED`], the title, the buttons
+    /// and the shadow. The last change wins, a later change of the activated bit from
+    /// [`DecorationsFrame::update_state`] replaces it.
+    pub fn set_active(&mut self, active: bool) {
+        if self.state.contains(WindowState::ACTIVATED) != active {
+            self.state.set(WindowState::ACTIVATED, active);
+            self.invalidate_header();
+        }
+    }
+
+    /// Scrolling on the frame, call it with the discrete steps of the axis, such as
+    /// `value120 / 120`, so one wheel notch acts once
+    ///
+    /// Only the header bar away from the buttons acts, with [`GtkFrameConfig::scroll_action`]
+    /// which does nothing by default. xdg-shell has no window shading, so a kwin like shade on
+    /// scroll maps to [`TitlebarAction::Minimize`].
+    pub fn on_scroll(&mut self, delta: f64) -> Option<FrameAction> {
+        if delta == 0.0 || self.mouse.location != Location::Head {
+            return None;
+        }
+
+        self.mouse
+            .secondary_click(self.config.scroll_action, true, &self.wm_capabilities)
+    }
+
+    /// A touch point went down on one of the frame surfaces, it acts like a normal click at the
+    /// touch position
+    ///
+    /// The touch point is tracked apart from the pointer, the pointer hover state is kept.
+    pub fn on_touch_down(
+        &mut self,
+        timestamp: Duration,
+        surface_id: &ObjectId,
+        id: i32,
+        x: f64,
+        y: f64,
+    ) -> Option<FrameAction> {
+        let (width, height) = match (self.width, self.height) {
+            (Some(width), Some(height)) => (width.get(), height.get()),
+            _ => return None,
+        };
+
+        let cursor_area = self.get_cursor_area(surface_id);
+        let location = self.mouse_location(x, y, width, height, cursor_area);
+        if location == Location::None {
+            return None;
+        }
+
+        let touch = TouchPoint {
+            id,
+            location,
+            pos: (x, y),
+        };
+
+        let action = self.mouse.touch_down(
+            touch,
+            timestamp,
+            self.config.double_click_duration,
+            self.resizable,
+            &self.state,
+            &self.wm_capabilities,
+        );
+
+        self.set_pressed_button(true, location);
+
+        action
+    }
+
+    /// The touch point went up, buttons are activated when the touch point went down on them
+    pub fn on_touch_up(&mut self, timestamp: Duration, id: i32) -> Option<FrameAction> {
+        let location = self.mouse.touch.filter(|touch| touch.id == id)?.location;
+
+        let action = self.mouse.touch_up(
+            id,
+            timestamp,
+            self.config.double_click_duration,
+            self.resizable,
+            &self.state,
+            &self.wm_capabilities,
+        );
+
+        if self.mouse.touch.is_none() {
+            self.set_pressed_button(false, Location::None);
+        }
+
+        self.button_activated(location, action)
+    }
+
+    /// The compositor cancelled the touch sequence, a pressed button is released without action
+    pub fn on_touch_cancel(&mut self) {
+        if self.mouse.touch.is_some() {
+            self.mouse.touch_cancel();
+            self.set_pressed_button(false, Location::None);
+        }
+    }
+
+    /// Set the size of the resize grab area along the edges, default is 5
+    ///
+    /// Negative values are treated as 0.
+    pub fn set_resize_edge_threshold(&mut self, threshold: f64) {
+        self.config.resize_edge_threshold = threshold.max(0.0);
+    }
+
+    /// The size of the resize grab area along the edges
+    pub fn resize_edge_threshold(&self) -> f64 {
+        self.config.resize_edge_threshold
+    }
+
+    /// Whether the header bar is fully opaque, it is only known with an opaque custom background
+    fn header_opaque(&self) -> bool {
+        // The custom renderer may leave transparent pixels.
+        self.header_renderer.is_none()
+            && self
+                .header_theme
+                .background
+                .is_some_and(|background| background.is_opaque())
+    }
+
+    /// The color of the title and button icons, `None` uses the gtk theme
+    #[cfg(feature = "gtk")]
+    fn foreground_color(&self) -> Option<Color> {
+        self.header_theme.foreground_color()
+    }
+
+    fn get_cursor_area(&mut self, surface_id: &ObjectId) -> CursorArea {
+        if self.header_bar_surface.id() == *surface_id {
+            CursorArea::Frame
+        } else {
+            match self
+                .shadow_surfaces
+                .iter()
+                .enumerate()
+                .find_map(|(index, shadow_surface)| {
+                    (shadow_surface.surface.id() == *surface_id).then_some(index)
+                }) {
+                None => CursorArea::Window,
+                Some(index) => {
+                    if index == ShadowPart::Top.index() {
+                        CursorArea::TopShadow
+                    } else if index == ShadowPart::Left.index() {
+                        CursorArea::LeftShadow
+                    } else if index == ShadowPart::Right.index() {
+                        CursorArea::RightShadow
+                    } else if index == ShadowPart::Bottom.index() {
+                        CursorArea::BottomShadow
+                    } else {
+                        unreachable!()
+                    }
+                }
+            }
+        }
+    }
+
+    /// Buttons state of both sides
+    fn buttons(&self) -> impl Iterator<Item = &ButtonState> {
+        self.left_buttons.iter().chain(&self.right_buttons)
+    }
+
+    /// Damage the buttons when the hovered one changed, the motion inside a button or over a
+    /// spacer, which has no hover look, doesn't make the frame dirty
+    fn update_dirty_by_button_cursor_pos(&mut self) {
+        let hovered_button = self
+            .mouse
+            .hovered_button()
+            .filter(|kind| *kind != ButtonKind::Spacer);
+
+        if self.hovered_button == hovered_button {
+            return;
+        }
+
+        // Both the old and the new hovered buttons are redrawn.
+        let old_hovered_button = mem::replace(&mut self.hovered_button, hovered_button);
+        for kind in old_hovered_button.into_iter().chain(hovered_button) {
+            self.damage_button(kind);
+        }
+    }
+
+    /// Damage only the button rect on the next draw
+    fn damage_button(&mut self, button_kind: ButtonKind) {
+        if let Some(state) = self
+            .buttons()
+            .find(|state| state.button_kind == button_kind)
+        {
+            let rect = (state.x, state.y, state.width as i32, state.height as i32);
+            push_damage(&mut self.button_damage, rect);
+        }
+    }
+
+    /// Whether the compositor supports the action of the button, unsupported buttons are hidden
+    fn button_supported(&self, button_kind: ButtonKind) -> bool {
+        match button_kind {
+            ButtonKind::Minimize => self
+                .wm_capabilities
+                .contains(WindowManagerCapabilities::MINIMIZE),
+            ButtonKind::Maximize => self
+                .wm_capabilities
+                .contains(WindowManagerCapabilities::MAXIMIZE),
+            _ => true,
+        }
+    }
+
+    /// Set the shadow and visible border theme
+    pub fn set_shadow_theme(&mut self, shadow_theme: ShadowTheme) {
+        if self.config.shadow_theme != shadow_theme {
+            self.config.shadow_theme = shadow_theme;
+            self.dirty = true;
+            self.shadow_should_sync = true;
+        }
+    }
+
+    /// Set whether to draw the drop shadow, on tiling compositors or low power devices the shadow
+    /// is pure overhead
+    ///
+    /// Resizing still works from the header bar and the window edges when it is disabled.
+    pub fn set_shadow_enabled(&mut self, shadow_enabled: bool) {
+        if self.config.shadow_enabled == shadow_enabled {
+            return;
+        }
+
+        self.config.shadow_enabled = shadow_enabled;
+        if !shadow_enabled {
+            self.detach_shadow();
+        }
+
+        self.dirty = true;
+        self.shadow_should_sync = true;
+    }
+
+    /// Set the translated tooltips and accessible names of the titlebar buttons
+    pub fn set_labels(&mut self, labels: ButtonLabels) {
+        if self.config.labels != labels {
+            self.config.labels = labels;
+            self.invalidate_header();
+        }
+    }
+
+    /// The tooltips and accessible names of the titlebar buttons, apps which expose the
+    /// decorations in their own accessibility tree can reuse them
+    pub fn labels(&self) -> &ButtonLabels {
+        &self.config.labels
+    }
+
+    /// Set the icon size of the titlebar buttons, `None` picks it from the header bar height
+    #[cfg(feature = "gtk")]
+    pub fn set_button_icon_size(&mut self, icon_size: Option<IconSize>) {
+        if self.config.button_icon_size != icon_size {
+            self.config.button_icon_size = icon_size;
+            self.invalidate_header();
+        }
+    }
+
+    /// The window surface entered an output with the integer `scale`, call it on every
+    /// `wl_surface.enter` of the main surface
+    ///
+    /// The frame is drawn at the largest scale of the outputs the window is on, like the main
+    /// surface on mixed DPI setups.
+    pub fn surface_entered_output(&mut self, scale: i32) {
+        self.output_scales.push(scale);
+        self.apply_output_scale();
+    }
+
+    /// The window surface left an output with the integer `scale`, call it on every
+    /// `wl_surface.leave` of the main surface, see [`GtkFrame::surface_entered_output`]
+    pub fn surface_left_output(&mut self, scale: i32) {
+        if let Some(index) = self.output_scales.iter().position(|s| *s == scale) {
+            self.output_scales.swap_remove(index);
+        }
+
+        self.apply_output_scale();
+    }
+
+    /// A decoration surface got `wl_surface.preferred_buffer_scale`, call it from
+    /// `CompositorHandler::scale_factor_changed`, return whether `surface` is a decoration
+    /// surface so the app can skip it
+    ///
+    /// The scale is only taken from `wl_surface` version 6 and newer, older compositors keep
+    /// [`DecorationsFrame::set_scaling_factor`] working.
+    pub fn preferred_buffer_scale_changed(&mut self, surface: &WlSurface, scale: i32) -> bool {
+        let is_decoration = self.header_bar_surface == *surface
+            || self
+                .shadow_surfaces
+                .iter()
+                .any(|shadow_surface| shadow_surface.surface == *surface);
+
+        if !is_decoration {
+            return false;
+        }
+
+        // Before version 6 the scale is guessed from the outputs the subsurface entered.
+        if surface.version() < 6 {
+            return true;
+        }
+
+        if scale > 0 && scale as f64 != self.scale_factor {
+            debug!(scale, "decoration surface preferred buffer scale changed");
+
+            self.set_scaling_factor(scale as f64);
+        }
+
+        true
+    }
+
+    /// Use the largest output scale, the last scale is kept when the window left all outputs
+    fn apply_output_scale(&mut self) {
+        let Some(scale) = self.output_scales.iter().copied().max() else {
+            return;
+        };
+
+        if scale as f64 != self.scale_factor {
+            self.set_scaling_factor(scale as f64);
+        }
+    }
+
+    /// Set the preferred scale from `wp_fractional_scale_v1`, such as `1.5`
+    ///
+    /// The header bar is rendered at this scale and mapped to the logical size with
+    /// `wp_viewport`, see [`GtkFrameBuilder::viewporter`]. Without the viewporter the scale is
+    /// rounded up to an integer buffer scale.
+    pub fn set_fractional_scale(&mut self, scale: f64) {
+        let scale = scale.clamp(0.1, 64.);
+        if self.fractional_scale != Some(scale) {
+            self.fractional_scale = Some(scale);
+            self.dirty = true;
+            self.header_should_sync = true;
+            self.shadow_should_sync = true;
+        }
+    }
+
+    /// The scale preferred by the compositor
+    fn preferred_scale(&self) -> f64 {
+        self.fractional_scale.unwrap_or(self.scale_factor)
+    }
+
+    /// The integer scale the buffers are rendered at, `wl_surface` only takes an integer buffer
+    /// scale so fractional scales are rounded up
+    fn buffer_scale(&self) -> u32 {
+        self.preferred_scale().ceil() as u32
+    }
+
+    /// The scale the header bar is rendered at, it is fractional only when the viewport can map
+    /// the buffer to the logical size
+    fn header_scale(&self) -> f64 {
+        if self.header_bar_viewport.is_some() {
+            self.preferred_scale()
+        } else {
+            self.buffer_scale() as f64
+        }
+    }
+
+    /// The resize grab size around the edges, in surface local coordinates
+    fn edge_threshold(&self) -> f64 {
+        self.config.resize_edge_threshold * self.preferred_scale()
+    }
+
+    fn set_resize_edge(&mut self, resize_edge: Option<Location>) {
+        if self.resize_edge != resize_edge {
+            self.resize_edge = resize_edge;
+            self.shadow_dirty = true;
+        }
+    }
+
+    /// Press the button at the location, or release all the buttons
+    fn set_pressed_button(&mut self, pressed: bool, location: Location) {
+        for state in self.left_buttons.iter_mut().chain(&mut self.right_buttons) {
+            // A spacer is only a gap, pressing it changes nothing.
+            let button_pressed = pressed
+                && state.button_kind != ButtonKind::Spacer
+                && location == Location::Button(state.button_kind);
+            if state.pressed != button_pressed {
+                state.pressed = button_pressed;
+                push_damage(
+                    &mut self.button_damage,
+                    (state.x, state.y, state.width as i32, state.height as i32),
+                );
+            }
+        }
+    }
+
+    fn mouse_location(
+        &self,
+        x: f64,
+        y: f64,
+        width: u32,
+        height: u32,
+        cursor_area: CursorArea,
+    ) -> Location {
+        let threshold = self.edge_threshold();
+        let (width, height) = (width as f64, height as f64);
+
+        match cursor_area {
+            CursorArea::Frame => {
+                if x <= threshold && y <= threshold {
+                    Location::TopLeft
+                } else if x >= width - threshold && y <= threshold {
+                    Location::TopRight
+                } else if x > threshold && x < width - threshold && y < threshold {
+                    Location::Top
+                } else {
+                    let scale = self.header_scale();
+                    for state in self.buttons() {
+                        // Spacers only take room, they can't be clicked.
+                        if state.button_kind != ButtonKind::Spacer
+                            && self.button_supported(state.button_kind)
+                            && Self::in_button((x, y), state, scale)
+                        {
+                            return Location::Button(state.button_kind);
+                        }
+                    }
+
+                    Location::Head
+                }
+            }
+
+            CursorArea::TopShadow => {
+                // The shadow starts at the left of the window.
+                let x = x - self.config.border_size as f64;
+
+                if x <= threshold {
+                    Location::TopLeft
+                } else if x >= width - threshold {
+                    Location::TopRight
+                } else {
+                    Location::Top
+                }
+            }
+
+            CursorArea::BottomShadow => {
+                let x = x - self.config.border_size as f64;
+
+                if x <= threshold {
+                    Location::BottomLeft
+                } else if x >= width - threshold {
+                    Location::BottomRight
+                } else {
+                    Location::Bottom
+                }
+            }
+
+            CursorArea::LeftShadow => {
+                // The side shadows also cover the header bar.
+                let height = height + self.header_size as f64;
+
+                if y <= threshold {
+                    Location::TopLeft
+                } else if y >= height - threshold {
+                    Location::BottomLeft
+                } else {
+                    Location::Left
+                }
+            }
+
+            CursorArea::RightShadow => {
+                let height = height + self.header_size as f64;
+
+                if y <= threshold {
+                    Location::TopRight
+                } else if y >= height - threshold {
+                    Location::BottomRight
+                } else {
+                    Location::Right
+                }
+            }
+
+            CursorArea::Window => {
+                // The main surface is below the header bar, so it only has the side and bottom
+                // edges.
+                let left = x <= threshold;
+                let right = x >= width - threshold;
+                let bottom = y >= height - threshold;
+
+                match (left, right, bottom) {
+                    (true, _, true) => Location::BottomLeft,
+                    (_, true, true) => Location::BottomRight,
+                    (true, _, false) => Location::Left,
+                    (_, true, false) => Location::Right,
+                    (false, false, true) => Location::Bottom,
+                    (false, false, false) => Location::None,
+                }
+            }
+        }
+    }
+
+    fn draw_head_bar(&mut self) -> anyhow::Result<bool> {
         // Reset the dirty bit.
-        self.dirty = false;
-        let should_sync = mem::take(&mut self.should_sync);
+        let button_damage = mem::take(&mut self.button_damage);
+        let full_damage = mem::take(&mut self.dirty) || button_damage.is_empty();
+        let should_sync = mem::take(&mut self.header_should_sync);
 
         // Don't draw borders if the frame explicitly hidden or fullscreened.
         if self.state.contains(WindowState::FULLSCREEN) {
@@ -498,112 +1696,617 @@ impl GtkFrame {
             Some(width) => width,
         };
 
-        let width = width.get() * self.scale_factor;
-        let height = HEADER_SIZE * self.scale_factor;
-        let (buffer, canvas) = self.pool.create_buffer(
-            width as _,
-            height as _,
-            (width * 4) as _,
-            wl_shm::Format::Argb8888,
+        let logical_width = width.get();
+        let scale = self.header_scale();
+        let width = physical_size(logical_width, scale);
+        let height = physical_size(self.header_size, scale);
+
+        let prepared_header = self.prepare_header(width, height)?;
+
+        // An opaque header bar doesn't need the alpha channel, so the compositor can skip the
+        // blending.
+        let opaque = self.header_opaque();
+        let shm_format = if opaque {
+            wl_shm::Format::Xrgb8888
+        } else {
+            wl_shm::Format::Argb8888
+        };
+
+        let (buffer, canvas) = cached_buffer(
+            &mut self.pool,
+            &mut self.header_bar_buffer,
+            width,
+            height,
+            shm_format,
         )?;
 
-        let image_surface = unsafe {
-            ImageSurface::create_for_data_unsafe(
-                canvas.as_mut_ptr() as _,
-                Format::ARgb32,
-                width as _,
-                height as _,
-                (width * 4) as _,
-            )?
+        let result = paint_header(&prepared_header, canvas, width, height, opaque);
+        #[cfg(feature = "gtk")]
+        if let PreparedHeader::Widgets(header_widgets) = prepared_header {
+            self.store_header_widgets(header_widgets);
+        }
+        result?;
+
+        if should_sync {
+            self.header_bar_subsurface.set_sync();
+        } else {
+            self.header_bar_subsurface.set_desync();
+        }
+
+        match &self.header_bar_viewport {
+            None => self.header_bar_surface.set_buffer_scale(scale as _),
+
+            Some(viewport) => {
+                self.header_bar_surface.set_buffer_scale(1);
+                viewport.set_destination(logical_width as _, self.header_size as _);
+            }
+        }
+        self.header_bar_subsurface
+            .set_position(0, -(self.header_size as i32));
+
+        // The region covers any size, the compositor clips it to the surface.
+        match &self.opaque_region {
+            Some(opaque_region) if opaque => self
+                .header_bar_surface
+                .set_opaque_region(Some(opaque_region.wl_region())),
+            _ => self.header_bar_surface.set_opaque_region(None),
+        }
+        buffer.attach_to(&self.header_bar_surface)?;
+
+        if self.header_bar_surface.version() < 4 {
+            self.header_bar_surface.damage(0, 0, i32::MAX, i32::MAX);
+        } else if full_damage {
+            self.header_bar_surface
+                .damage_buffer(0, 0, i32::MAX, i32::MAX);
+        } else {
+            // Only the hover or pressed state of buttons changed, the allocations are in buffer
+            // coordinates.
+            for (x, y, width, height) in button_damage {
+                self.header_bar_surface.damage_buffer(x, y, width, height);
+            }
+        }
+
+        self.header_bar_surface.commit();
+
+        Ok(should_sync)
+    }
+
+    /// Render the header bar into `canvas` without a wayland surface, such as in tests
+    ///
+    /// `width` and `height` are in physical pixels, `canvas` holds native endian ARGB32 pixels
+    /// with a stride of `width * 4`, or XRGB32 when the header bar has an opaque background.
+    /// The rects of the packed buttons are returned in the same pixels.
+    pub fn render_header(
+        &mut self,
+        canvas: &mut [u8],
+        width: u32,
+        height: u32,
+    ) -> anyhow::Result<Vec<ButtonRect>> {
+        let prepared_header = self.prepare_header(width, height)?;
+
+        let result = paint_header(
+            &prepared_header,
+            canvas,
+            width,
+            height,
+            self.header_opaque(),
+        );
+        #[cfg(feature = "gtk")]
+        if let PreparedHeader::Widgets(header_widgets) = prepared_header {
+            self.store_header_widgets(header_widgets);
+        }
+        result?;
+
+        Ok(self
+            .buttons()
+            .filter(|state| state.width > 0 && state.height > 0)
+            .map(|state| ButtonRect {
+                kind: state.button_kind,
+                x: state.x,
+                y: state.y,
+                width: state.width,
+                height: state.height,
+            })
+            .collect())
+    }
+
+    /// Lay out the header bar with the custom renderer, or gtk, or tiny-skia without gtk
+    fn prepare_header(&mut self, width: u32, height: u32) -> anyhow::Result<PreparedHeader> {
+        let Some(mut renderer) = self.header_renderer.take() else {
+            #[cfg(feature = "gtk")]
+            return Ok(PreparedHeader::Widgets(
+                self.prepare_header_widgets(width, height),
+            ));
+
+            #[cfg(not(feature = "gtk"))]
+            return self
+                .render_custom_header(&mut SkiaHeaderRenderer::new(), width, height)
+                .map(PreparedHeader::Pixmap);
         };
-        let cairo_context = Context::new(image_surface)?;
-        let header_bar = self.create_head_bar();
 
-        let buttons = self
+        let pixmap = self.render_custom_header(&mut **renderer, width, height);
+        self.header_renderer = Some(renderer);
+
+        Ok(PreparedHeader::Pixmap(pixmap?))
+    }
+
+    /// Render the header bar with the custom renderer and read the button rects
+    fn render_custom_header(
+        &mut self,
+        renderer: &mut dyn HeaderRenderer,
+        width: u32,
+        height: u32,
+    ) -> anyhow::Result<Pixmap> {
+        check_buffer_size(width, height)?;
+        let mut pixmap = Pixmap::new(width, height)
+            .ok_or_else(|| anyhow::anyhow!("invalid header bar size {width}x{height}"))?;
+
+        let title = match &self.title_prefix {
+            None => self.title.clone(),
+            Some(prefix) => format!("{prefix}{}", self.title),
+        };
+        let supported_buttons = |states: &[ButtonState]| {
+            states
+                .iter()
+                .map(|state| state.button_kind)
+                .filter(|kind| self.button_supported(*kind))
+                .collect::<Vec<_>>()
+        };
+        let left_buttons = supported_buttons(&self.left_buttons);
+        let right_buttons = supported_buttons(&self.right_buttons);
+
+        let context = HeaderContext {
+            scale: self.header_scale(),
+            title: &title,
+            subtitle: self.subtitle.as_deref(),
+            left_buttons: &left_buttons,
+            right_buttons: &right_buttons,
+            hovered_button: self.mouse.hovered_button(),
+            pressed_button: self
+                .buttons()
+                .find(|state| state.pressed)
+                .map(|state| state.button_kind),
+            state: self.state,
+            wm_capabilities: self.wm_capabilities,
+            color_scheme: self.color_scheme,
+            header_theme: self.header_theme,
+        };
+
+        let rects = renderer.render(&context, &mut pixmap.as_mut());
+
+        for state in self.left_buttons.iter_mut().chain(&mut self.right_buttons) {
+            match rects.iter().find(|rect| rect.kind == state.button_kind) {
+                None => {
+                    state.width = 0;
+                    state.height = 0;
+                }
+
+                Some(rect) => {
+                    state.x = rect.x;
+                    state.y = rect.y;
+                    state.width = rect.width;
+                    state.height = rect.height;
+                }
+            }
+        }
+
+        Ok(pixmap)
+    }
+
+    /// Draw the header bar with `renderer` instead of gtk, `None` goes back to gtk, or to
+    /// [`SkiaHeaderRenderer`] without the `gtk` feature
+    ///
+    /// The gtk only options, such as the header bar hook, the css and the title font, don't
+    /// apply to a custom renderer.
+    pub fn set_header_renderer(&mut self, renderer: Option<Box<dyn HeaderRenderer>>) {
+        // A frame built without gtk can't go back to it.
+        #[cfg(feature = "gtk")]
+        if renderer.is_none() && !gtk::is_initialized_main_thread() {
+            warn!("gtk is not inited, keep the custom header renderer");
+
+            return;
+        }
+
+        self.header_renderer = renderer.map(Callback);
+        self.invalidate_header();
+    }
+
+    /// Build or reuse the header bar widgets at the physical size, lay them out and read the
+    /// button allocations
+    #[cfg(feature = "gtk")]
+    fn prepare_header_widgets(&mut self, width: u32, height: u32) -> HeaderWidgets {
+        if !gtk_theme_loaded() {
+            debug!("gtk theme is not loaded yet, head bar may be drawn with fallback style");
+        }
+
+        let header_widgets = match self.header_widgets.take() {
+            Some(header_widgets) => header_widgets,
+            None => self.create_header_widgets(width, height),
+        };
+        header_widgets
+            .offscreen_window
+            .resize(width as _, height as _);
+
+        // make sure gtk can draw cairo context
+        match &mut self.gtk_pump {
+            None => pump_gtk_events(),
+
+            Some(gtk_pump) => gtk_pump(),
+        }
+
+        for (button, state) in header_widgets
             .buttons
             .iter()
+            .zip(self.left_buttons.iter_mut().chain(&mut self.right_buttons))
+        {
+            // The button is not packed, it can't be clicked.
+            let Some(button) = button else {
+                state.width = 0;
+                state.height = 0;
+
+                continue;
+            };
+
+            let allocation = button.allocation();
+
+            state.x = allocation.x();
+            state.y = allocation.y();
+            state.width = allocation.width() as _;
+            state.height = allocation.height() as _;
+
+            Self::apply_button_state(&self.mouse, button, state, self.state);
+        }
+
+        header_widgets
+    }
+
+    /// The bytes of all the buffers at the current size
+    fn needed_pool_size(&self) -> usize {
+        let mut size = 0;
+
+        if let Some(width) = self.width {
+            let scale = self.header_scale();
+            size += physical_size(width.get(), scale) as usize
+                * physical_size(self.header_size, scale) as usize;
+        }
+
+        let buffer_scale = self.buffer_scale();
+        for shadow_surface in &self.shadow_surfaces {
+            let scale = shadow_scale(shadow_surface, buffer_scale) as f64;
+            size += physical_size(shadow_surface.width, scale) as usize
+                * physical_size(shadow_surface.height, scale) as usize;
+        }
+
+        size * 4
+    }
+
+    /// Replace the pool with a smaller one when the buffers need much less memory, such as after
+    /// a maximized window is restored
+    ///
+    /// The pool only grows, so it is recreated at the needed size once it is more than
+    /// [`POOL_SHRINK_FACTOR`] times larger. The gap between the factor and the size the pool
+    /// grows to keeps rapid resizes from recreating the pool again and again, and small pools
+    /// are never shrunk.
+    fn shrink_pool(&mut self) {
+        let needed = self.needed_pool_size();
+        let len = self.pool.len();
+
+        if len <= MIN_SHRINK_POOL_SIZE || len <= needed.saturating_mul(POOL_SHRINK_FACTOR) {
+            return;
+        }
+
+        let pool = match SlotPool::new(needed.max(1), &self.shm) {
+            Err(err) => {
+                warn!(%err, "create smaller pool failed");
+
+                return;
+            }
+
+            Ok(pool) => pool,
+        };
+
+        debug!(len, needed, "shrink the buffer pool");
+
+        // The buffers of the old pool are replaced by the draw right after.
+        self.pool = pool;
+        self.header_bar_buffer = None;
+        self.shadow_buffers = Default::default();
+        for shadow_surface in &mut self.shadow_surfaces {
+            shadow_surface.content = None;
+        }
+        self.dirty = true;
+    }
+
+    /// Keep the laid out header bar widgets and measure the minimum width they need
+    #[cfg(feature = "gtk")]
+    fn store_header_widgets(&mut self, header_widgets: HeaderWidgets) {
+        let (minimum_width, _) = header_widgets.offscreen_window.preferred_width();
+        self.min_frame_width = (minimum_width.max(0) as f64 / self.header_scale()).ceil() as u32;
+        self.header_widgets = Some(header_widgets);
+    }
+
+    /// Mark the header bar widgets outdated, they are rebuilt on the next draw
+    fn invalidate_header(&mut self) {
+        #[cfg(feature = "gtk")]
+        self.header_widgets = None;
+        self.dirty = true;
+    }
+
+    #[cfg(feature = "gtk")]
+    fn create_header_widgets(&mut self, width: u32, height: u32) -> HeaderWidgets {
+        let header_bar = self.create_head_bar();
+
+        let left_buttons = self
+            .left_buttons
+            .iter()
             .map(|button_state| {
-                let button = match button_state.button_kind {
-                    ButtonKind::Close => self.create_close_button(),
-                    ButtonKind::Maximize => self.create_max_button(),
-                    ButtonKind::Minimize => self.create_min_button(),
-                };
+                if !self.button_supported(button_state.button_kind) {
+                    return None;
+                }
 
-                button.show_all();
+                let button = self.create_button(button_state.button_kind);
+                header_bar.pack_start(&button);
 
-                if self.buttons_at_end {
-                    header_bar.pack_end(&button);
-                } else {
-                    header_bar.pack_start(&button);
+                Some(button)
+            })
+            .collect::<Vec<_>>();
+
+        let right_buttons = self
+            .right_buttons
+            .iter()
+            .map(|button_state| {
+                if !self.button_supported(button_state.button_kind) {
+                    return None;
                 }
 
-                button
+                let button = self.create_button(button_state.button_kind);
+                header_bar.pack_end(&button);
+
+                Some(button)
             })
             .collect::<Vec<_>>();
 
+        if let Some(header_bar_hook) = &mut self.header_bar_hook {
+            header_bar_hook(&header_bar);
+        }
+
+        if let Some(custom_css) = &self.custom_css {
+            add_provider_recursive(
+                &header_bar,
+                custom_css,
+                gtk::STYLE_PROVIDER_PRIORITY_APPLICATION,
+            );
+        }
+
+        set_direction_recursive(&header_bar, self.direction);
+
         let offscreen_window = OffscreenWindow::new();
         offscreen_window.set_default_size(width as _, height as _);
         offscreen_window.add(&header_bar);
-        offscreen_window.show_all();
 
-        for (button, state) in buttons.into_iter().zip(&mut self.buttons) {
-            let allocation = button.allocation();
+        // The header bar follows the system color scheme like a libadwaita app. The dark theme
+        // goes on the frame widgets only, the process wide prefer dark setting belongs to the app.
+        if self.color_scheme.is_dark() {
+            if let Some(dark_theme) = dark_theme_provider() {
+                // Above the screen theme, below the settings and the custom css.
+                add_provider_recursive(
+                    &offscreen_window,
+                    &dark_theme,
+                    gtk::STYLE_PROVIDER_PRIORITY_THEME + 1,
+                );
+            }
+        }
 
-            state.x = allocation.x();
-            state.y = allocation.y();
-            state.width = allocation.width() as _;
-            state.height = allocation.height() as _;
+        // An unfocused gtk window is in the backdrop state, the flag propagates to all the
+        // widgets, so the title, the icon and the hook widgets are dimmed with the header bar.
+        if !self.state.contains(WindowState::ACTIVATED) {
+            offscreen_window.set_state_flags(StateFlags::BACKDROP, false);
+        }
+
+        offscreen_window.show_all();
 
-            Self::apply_button_state(&self.mouse, &button, state, self.state);
+        let (minimum_height, _) = header_bar.preferred_height();
+        if minimum_height > height as i32 {
+            warn!(
+                minimum_height,
+                height, "header bar content is taller than the header size, it is clipped"
+            );
         }
 
-        // make sure gtk can draw cairo context
-        while gtk::events_pending() {
-            gtk::main_iteration();
+        HeaderWidgets {
+            offscreen_window,
+            buttons: left_buttons.into_iter().chain(right_buttons).collect(),
         }
+    }
+
+    #[cfg(feature = "gtk")]
+    fn create_head_bar(&mut self) -> HeaderBar {
+        let title = match &self.title_prefix {
+            None => self.title.clone(),
+            Some(prefix) => format!("{prefix}{}", self.title),
+        };
 
-        offscreen_window.draw(&cairo_context);
+        let header_bar = HeaderBar::new();
+        header_bar.set_custom_title(Some(&self.create_title_widget(&title)));
 
-        if should_sync {
-            self.header_bar_subsurface.set_sync();
-        } else {
-            self.header_bar_subsurface.set_desync();
+        if let Some(background) = self.header_theme.background {
+            let css = format!(
+                "headerbar {{ background-image: none; background-color: {}; }}",
+                css_color(background)
+            );
+
+            apply_widget_css(&header_bar, &css);
         }
 
-        self.header_bar_surface
-            .set_buffer_scale(self.scale_factor as _);
-        self.header_bar_subsurface
-            .set_position(0, -(HEADER_SIZE as i32));
-        buffer.attach_to(&self.header_bar_surface)?;
+        if self.config.compact {
+            apply_widget_css(
+                &header_bar,
+                "headerbar { min-height: 0; padding-top: 0; padding-bottom: 0; }",
+            );
+        }
 
-        if self.header_bar_surface.version() >= 4 {
-            self.header_bar_surface
-                .damage_buffer(0, 0, i32::MAX, i32::MAX);
-        } else {
-            self.header_bar_surface.damage(0, 0, i32::MAX, i32::MAX);
+        // Without an icon in the button layout, put the icon opposite the buttons.
+        if !self
+            .buttons()
+            .any(|state| state.button_kind == ButtonKind::Icon)
+        {
+            if let Some(icon) = self.create_window_icon() {
+                if self.left_buttons.is_empty() {
+                    header_bar.pack_start(&icon);
+                } else {
+                    header_bar.pack_end(&icon);
+                }
+            }
         }
 
-        self.header_bar_surface.commit();
+        header_bar
+    }
 
-        Ok(should_sync)
+    #[cfg(feature = "gtk")]
+    fn create_button(&self, button_kind: ButtonKind) -> Widget {
+        let button = match button_kind {
+            ButtonKind::Close => self.create_close_button().upcast::<Widget>(),
+            ButtonKind::Maximize => self.create_max_button().upcast(),
+            ButtonKind::Minimize => self.create_min_button().upcast(),
+            ButtonKind::Menu => self.create_menu_button().upcast(),
+            ButtonKind::Spacer => Self::create_spacer(SPACER_SIZE).upcast(),
+            ButtonKind::Icon => match self.create_window_icon() {
+                Some(icon) => icon.upcast(),
+                // Nothing to show, keep an empty widget so the button states still match
+                None => Self::create_spacer(0).upcast(),
+            },
+        };
+
+        button.show_all();
+
+        button
     }
 
-    fn create_head_bar(&mut self) -> HeaderBar {
-        let header_bar = HeaderBar::builder().title(&self.title).build();
-        if !self.state.contains(WindowState::ACTIVATED) {
-            let style_context = header_bar.style_context();
-            let mut state_flags = style_context.state();
-            state_flags |= StateFlags::BACKDROP;
-            style_context.set_state(state_flags);
+    /// The title label, with the subtitle below it like the gtk title box
+    #[cfg(feature = "gtk")]
+    fn create_title_widget(&self, title: &str) -> Widget {
+        let label = self.create_title_label(title);
+
+        if let Some(title_font) = &self.title_font {
+            let attrs = AttrList::new();
+            attrs.insert(AttrFontDesc::new(title_font));
+            label.set_attributes(Some(&attrs));
         }
 
-        header_bar
+        let Some(subtitle) = &self.subtitle else {
+            return label.upcast();
+        };
+
+        let subtitle_label = self.create_title_label(subtitle);
+        let style_context = subtitle_label.style_context();
+        style_context.remove_class("title");
+        style_context.add_class("subtitle");
+        style_context.add_class("dim-label");
+
+        let title_box = GtkBox::new(Orientation::Vertical, 0);
+        title_box.set_valign(Align::Center);
+        title_box.add(&label);
+        title_box.add(&subtitle_label);
+
+        title_box.upcast()
+    }
+
+    #[cfg(feature = "gtk")]
+    fn create_title_label(&self, title: &str) -> Label {
+        let label = Label::new(Some(title));
+        // The title is plain text, so `&` and `<` in it are shown as they are.
+        label.set_use_markup(false);
+        label.set_use_underline(false);
+        label.set_single_line_mode(true);
+        label.set_line_wrap(false);
+        label.set_ellipsize(self.title_ellipsize);
+        label.style_context().add_class("title");
+
+        if let Some(foreground) = self.foreground_color() {
+            apply_widget_css(
+                &label,
+                &format!("label {{ color: {}; }}", css_color(foreground)),
+            );
+        }
+
+        // Margins are part of the title allocation, so the header bar keeps them between the
+        // title and the buttons, without changing the buttons allocation.
+        let gap = self.title_button_gap.min(i32::MAX as u32) as i32;
+        label.set_margin_start(gap);
+        label.set_margin_end(gap);
+
+        // The header bar gives all the space between the buttons to an expanded title, then
+        // the label aligns the text inside it.
+        match self.title_align {
+            TitleAlign::Center => {}
+            TitleAlign::Start => {
+                label.set_hexpand(true);
+                label.set_xalign(0.0);
+            }
+            TitleAlign::End => {
+                label.set_hexpand(true);
+                label.set_xalign(1.0);
+            }
+        }
+
+        label
+    }
+
+    #[cfg(feature = "gtk")]
+    fn create_window_icon(&self) -> Option<Image> {
+        let icon = self.window_icon.as_ref()?;
+
+        let (width, height) = (icon.width(), icon.height());
+        let icon = if width > WINDOW_ICON_SIZE || height > WINDOW_ICON_SIZE {
+            let ratio = WINDOW_ICON_SIZE as f64 / width.max(height) as f64;
+
+            icon.scale_simple(
+                ((width as f64 * ratio).round() as i32).max(1),
+                ((height as f64 * ratio).round() as i32).max(1),
+                InterpType::Bilinear,
+            )?
+        } else {
+            icon.clone()
+        };
+
+        let image = Image::from_pixbuf(Some(&icon));
+        image.set_valign(Align::Center);
+        image.style_context().add_class("titlebutton");
+        image.style_context().add_class("icon");
+
+        Some(image)
     }
 
-    fn draw_shadow(&mut self, should_sync: bool) -> anyhow::Result<()> {
-        let border_paint = self.shadow_theme.border_paint();
+    fn draw_shadow(&mut self) -> anyhow::Result<bool> {
+        let should_sync = mem::take(&mut self.shadow_should_sync);
+        self.shadow_dirty = false;
+
+        // The buffers are detached when the shadow is disabled or the frame is hidden.
+        if !self.config.shadow_enabled || self.hidden {
+            return Ok(should_sync);
+        }
+
+        // The shadow surfaces are sized by the first resize.
+        if self.width.is_none() || self.height.is_none() {
+            return Ok(false);
+        }
+
+        // No borders around a fullscreen window, like `location` and `add_borders`.
+        if self.state.contains(WindowState::FULLSCREEN) {
+            self.detach_shadow();
+
+            return Ok(should_sync);
+        }
+
+        let border_color = self.config.shadow_theme.border_color(self.color_scheme);
+        let highlight_color = self
+            .config
+            .shadow_theme
+            .edge_highlight_color(self.color_scheme);
+        let active = self.state.contains(WindowState::ACTIVATED);
+        let buffer_scale = self.buffer_scale();
 
-        for (shadow_part, shadow_surface) in [
+        for ((shadow_part, shadow_surface), shadow_buffer) in [
             ShadowPart::Top,
             ShadowPart::Left,
             ShadowPart::Right,
@@ -611,14 +2314,63 @@ impl GtkFrame {
         ]
         .iter()
         .zip(&mut self.shadow_surfaces)
+        .zip(&mut self.shadow_buffers)
         {
-            let width = shadow_surface.width * self.scale_factor;
-            let height = shadow_surface.height * self.scale_factor;
+            // Only the parts along a tiled edge are hidden, a half tiled window keeps the
+            // shadow on its free edges like gnome. A maximized window has square corners and
+            // no shadow at all, even when the compositor doesn't report it as tiled.
+            let params = (!self.state.contains(WindowState::MAXIMIZED)
+                && !self.state.contains(shadow_part.tiled_state())
+                && self.config.shadow_theme.style().has_part(*shadow_part))
+            .then(|| self.config.shadow_theme.shadow_params(active));
+
+            let scale = shadow_scale(shadow_surface, buffer_scale);
+
+            let highlighted = self
+                .resize_edge
+                .is_some_and(|edge| shadow_part.has_border_of(edge));
+            let border_color = if highlighted {
+                highlight_color
+            } else {
+                border_color
+            };
+
+            let content = ShadowContent {
+                width: shadow_surface.width,
+                height: shadow_surface.height,
+                scale,
+                border_size: self.config.border_size,
+                params,
+                border_color,
+            };
+
+            if should_sync {
+                shadow_surface.subsurface.set_sync();
+            } else {
+                shadow_surface.subsurface.set_desync();
+            }
+
+            shadow_surface
+                .subsurface
+                .set_position(shadow_surface.x, shadow_surface.y);
+
+            // Only the position changed, such as the right shadow when the window is wider, the
+            // compositor keeps showing the attached buffer without any damage.
+            if shadow_surface.content == Some(content) {
+                shadow_surface.surface.commit();
 
-            let (buffer, canvas) = self.pool.create_buffer(
-                width as _,
-                height as _,
-                (width * 4) as _,
+                continue;
+            }
+
+            let width = physical_size(shadow_surface.width, scale as f64);
+            let height = physical_size(shadow_surface.height, scale as f64);
+
+            // The shadow is transparent, it always needs the alpha channel.
+            let (buffer, canvas) = cached_buffer(
+                &mut self.pool,
+                shadow_buffer,
+                width,
+                height,
                 wl_shm::Format::Argb8888,
             )?;
 
@@ -630,77 +2382,57 @@ impl GtkFrame {
             // do invisible borders to enlarge the input zone.
             pixmap.fill(Color::TRANSPARENT);
 
-            if !self.state.intersects(WindowState::TILED) {
+            if let Some(params) = params {
                 self.shadow.draw(
                     &mut pixmap,
-                    self.scale_factor,
-                    self.state.contains(WindowState::ACTIVATED),
+                    self.config.border_size - VISIBLE_BORDER_SIZE,
+                    scale,
+                    params,
                     *shadow_part,
                 );
             }
 
-            // The visible border is one pt.
-            let visible_border_size = VISIBLE_BORDER_SIZE * self.scale_factor;
-
-            // XXX we do all the match using integral types and then convert to f32 in the
-            // end to ensure that result is finite.
-            let border_rect = match shadow_part {
-                ShadowPart::Left => {
-                    let x =
-                        (shadow_surface.x.unsigned_abs() * self.scale_factor) - visible_border_size;
-                    let y = shadow_surface.y.unsigned_abs() * self.scale_factor;
-                    Rect::from_xywh(
-                        x as f32,
-                        y as f32,
-                        visible_border_size as f32,
-                        (shadow_surface.height - y) as f32,
-                    )
-                }
-
-                ShadowPart::Right => {
-                    let y = shadow_surface.y.unsigned_abs() * self.scale_factor;
-                    Rect::from_xywh(
-                        0.,
-                        y as f32,
-                        visible_border_size as f32,
-                        (shadow_surface.height - y) as f32,
-                    )
-                }
-                // We draw small visible border only bellow the window surface, no need to
-                // handle `TOP`.
-                ShadowPart::Bottom => {
-                    let x =
-                        (shadow_surface.x.unsigned_abs() * self.scale_factor) - visible_border_size;
-                    Rect::from_xywh(
-                        x as f32,
-                        0.,
-                        (shadow_surface.width - 2 * x) as f32,
-                        visible_border_size as f32,
-                    )
-                }
-                _ => None,
-            };
+            let border_rect = visible_border_rect(
+                *shadow_part,
+                (shadow_surface.x, shadow_surface.y),
+                (width, height),
+                scale,
+            );
 
             // Fill the visible border, if present.
             if let Some(border_rect) = border_rect {
+                let border_paint = ShadowTheme::border_paint_for(border_color);
+
                 pixmap.fill_rect(border_rect, &border_paint, Transform::identity(), None);
             }
 
-            if should_sync {
-                shadow_surface.subsurface.set_sync();
-            } else {
-                shadow_surface.subsurface.set_desync();
+            // Without a viewport the shadow is rendered at the integer buffer scale.
+            match &shadow_surface.viewport {
+                None => shadow_surface.surface.set_buffer_scale(scale as _),
+
+                Some(viewport) => {
+                    shadow_surface.surface.set_buffer_scale(1);
+                    viewport.set_destination(shadow_surface.width as _, shadow_surface.height as _);
+                }
             }
 
-            shadow_surface
-                .surface
-                .set_buffer_scale(self.scale_factor as _);
+            if let Some(input_region) = &self.input_region {
+                let (x, y, width, height) =
+                    shadow_input_rect(shadow_surface, *shadow_part, self.config.border_size);
+
+                // Regions can only be added to or subtracted from, so clear the last rect first.
+                input_region.subtract(0, 0, i32::MAX, i32::MAX);
+                input_region.add(x, y, width, height);
+                shadow_surface
+                    .surface
+                    .set_input_region(Some(input_region.wl_region()));
+            }
 
-            shadow_surface
-                .subsurface
-                .set_position(shadow_surface.x, shadow_surface.y);
             buffer.attach_to(&shadow_surface.surface)?;
+            shadow_surface.content = Some(content);
+            shadow_surface.attached = true;
 
+            // The content changed, all of it is damaged.
             if shadow_surface.surface.version() >= 4 {
                 shadow_surface
                     .surface
@@ -712,114 +2444,714 @@ impl GtkFrame {
             shadow_surface.surface.commit();
         }
 
-        Ok(())
+        Ok(should_sync)
+    }
+
+    /// Attach null buffers to the shadow surfaces so they are not shown
+    fn detach_shadow(&mut self) {
+        for shadow_surface in &mut self.shadow_surfaces {
+            shadow_surface.content = None;
+
+            // Nothing to detach, such as on every draw of a fullscreen window.
+            if !mem::take(&mut shadow_surface.attached) {
+                continue;
+            }
+
+            shadow_surface.surface.attach(None, 0, 0);
+            shadow_surface.surface.commit();
+        }
     }
 
+    #[cfg(feature = "gtk")]
     fn apply_button_state(
         mouse: &MouseState,
-        button: &Button,
+        button: &Widget,
         button_state: &ButtonState,
         window_state: WindowState,
     ) {
         let style_context = button.style_context();
+        // The widgets are reused, so the flags of the previous draw are cleared.
         let mut state_flags = style_context.state();
+        state_flags.remove(StateFlags::BACKDROP | StateFlags::PRELIGHT | StateFlags::ACTIVE);
 
         if !window_state.contains(WindowState::ACTIVATED) {
             state_flags |= StateFlags::BACKDROP;
         }
 
-        if let Location::Button(kind) = mouse.location {
-            if button_state.button_kind == kind {
-                state_flags |= StateFlags::PRELIGHT;
+        if mouse.hovered_button() == Some(button_state.button_kind) {
+            state_flags |= StateFlags::PRELIGHT;
+        }
 
-                if mouse.button_pressed {
-                    state_flags |= StateFlags::ACTIVE;
-                }
-            }
+        if button_state.pressed {
+            state_flags |= StateFlags::ACTIVE;
         }
 
         style_context.set_state(state_flags);
     }
 
-    fn in_button(cursor_pos: (f64, f64), state: &ButtonState) -> bool {
-        cursor_pos.0 >= state.x as _
-            && cursor_pos.0 <= (state.x + state.width as i32) as _
-            && cursor_pos.1 >= state.y as _
-            && cursor_pos.1 <= (state.y + state.height as i32) as _
+    #[cfg(feature = "gtk")]
+    fn apply_button_style(&self, button: &Button) {
+        if self.config.compact {
+            let css = format!(
+                "button {{ min-width: {size}px; min-height: {size}px; padding: 0; margin: 0; }}",
+                size = COMPACT_BUTTON_SIZE,
+            );
+
+            apply_widget_css(button, &css);
+        }
+
+        if let Some(color) = self.header_theme.button_hover {
+            let css = format!(
+                "button:hover {{ background-image: none; background-color: {}; }}",
+                css_color(color)
+            );
+
+            apply_widget_css(button, &css);
+        }
+
+        // Symbolic icons are recolored with the inherited css color.
+        if let Some(foreground) = self.foreground_color() {
+            apply_widget_css(
+                button,
+                &format!("button {{ color: {}; }}", css_color(foreground)),
+            );
+        }
+    }
+
+    /// The button rect is in header bar buffer pixels, the cursor is in logical pixels
+    fn in_button(cursor_pos: (f64, f64), state: &ButtonState, scale: f64) -> bool {
+        let x = state.x as f64 / scale;
+        let y = state.y as f64 / scale;
+        let width = state.width as f64 / scale;
+        let height = state.height as f64 / scale;
+
+        cursor_pos.0 >= x
+            && cursor_pos.0 <= x + width
+            && cursor_pos.1 >= y
+            && cursor_pos.1 <= y + height
     }
 
+    #[cfg(feature = "gtk")]
     fn create_min_button(&self) -> Button {
         let button = Button::new();
         button.set_valign(Align::Center);
         let style_context = button.style_context();
         style_context.add_class("titlebutton");
         style_context.add_class("minimize");
-        let image = Image::from_icon_name(Some("window-minimize-symbolic"), IconSize::Menu);
+        set_button_label(&button, &self.config.labels.minimize);
+        let image =
+            Image::from_icon_name(Some("window-minimize-symbolic"), self.button_icon_size());
         image.set_use_fallback(true);
         button.add(&image);
         button.set_can_focus(false);
+        self.apply_button_style(&button);
 
         button
     }
 
+    #[cfg(feature = "gtk")]
     fn create_max_button(&self) -> Button {
         let button = Button::new();
         button.set_valign(Align::Center);
         let style_context = button.style_context();
         style_context.add_class("titlebutton");
 
-        let icon = if self.state.contains(WindowState::MAXIMIZED) {
-            "window-restore-symbolic"
+        let (icon, label) = if self.state.contains(WindowState::MAXIMIZED) {
+            ("window-restore-symbolic", &self.config.labels.restore)
         } else {
-            "window-maximize-symbolic"
+            ("window-maximize-symbolic", &self.config.labels.maximize)
         };
 
         style_context.add_class("maximize");
-        let image = Image::from_icon_name(Some(icon), IconSize::Menu);
+        set_button_label(&button, label);
+        let image = Image::from_icon_name(Some(icon), self.button_icon_size());
         image.set_use_fallback(true);
         button.add(&image);
         button.set_can_focus(false);
+        self.apply_button_style(&button);
 
         button
     }
 
+    #[cfg(feature = "gtk")]
+    fn create_menu_button(&self) -> Button {
+        let button = Button::new();
+        button.set_valign(Align::Center);
+        let style_context = button.style_context();
+        style_context.add_class("titlebutton");
+        style_context.add_class("appmenu");
+        let image = Image::from_icon_name(Some("open-menu-symbolic"), self.button_icon_size());
+        image.set_use_fallback(true);
+        button.add(&image);
+        button.set_can_focus(false);
+        self.apply_button_style(&button);
+
+        button
+    }
+
+    /// The icon size of the titlebar buttons, the configured one or the one that fits the
+    /// header bar height
+    #[cfg(feature = "gtk")]
+    fn button_icon_size(&self) -> IconSize {
+        self.config
+            .button_icon_size
+            .unwrap_or_else(|| icon_size_for_header(self.header_size))
+    }
+
+    #[cfg(feature = "gtk")]
+    fn create_spacer(width: i32) -> GtkBox {
+        let spacer = GtkBox::new(Orientation::Horizontal, 0);
+        spacer.set_size_request(width, -1);
+
+        spacer
+    }
+
+    #[cfg(feature = "gtk")]
     fn create_close_button(&self) -> Button {
         let button = Button::new();
         button.set_valign(Align::Center);
         let style_context = button.style_context();
         style_context.add_class("titlebutton");
         style_context.add_class("close");
-        let image = Image::from_icon_name(Some("window-close-symbolic"), IconSize::Menu);
+        set_button_label(&button, &self.config.labels.close);
+        let image = Image::from_icon_name(Some("window-close-symbolic"), self.button_icon_size());
         image.set_use_fallback(true);
         button.add(&image);
         button.set_can_focus(false);
+        self.apply_button_style(&button);
 
         button
     }
 }
 
-fn init_shadow_surfaces_pos(shadow_surfaces: &mut [ShadowSurface; 4]) {
+impl Drop for GtkFrame {
+    fn drop(&mut self) {
+        // Destroy the roles before the surfaces, the subsurfaces are unmapped immediately.
+        if let Some(viewport) = &self.header_bar_viewport {
+            viewport.destroy();
+        }
+        self.header_bar_subsurface.destroy();
+        self.header_bar_surface.destroy();
+
+        for shadow_surface in &self.shadow_surfaces {
+            if let Some(viewport) = &shadow_surface.viewport {
+                viewport.destroy();
+            }
+            shadow_surface.subsurface.destroy();
+            shadow_surface.surface.destroy();
+        }
+    }
+}
+
+/// Make sure gtk has loaded the theme and css, so the first frame is drawn with correct style
+#[cfg(feature = "gtk")]
+fn load_gtk_theme() {
+    if !gtk::is_initialized_main_thread() {
+        return;
+    }
+
+    // Getting the default settings makes gtk load the theme of the default screen
+    if gtk::Settings::default().is_none() {
+        warn!("gtk has no default settings, theme can't be loaded");
+
+        return;
+    }
+
+    pump_gtk_events();
+
+    if !gtk_theme_loaded() {
+        warn!("gtk theme is still not loaded after gtk init");
+    }
+}
+
+/// Measure the natural height of a gtk header bar with a title and a button, so the header
+/// size matches the theme, it is cached until the gtk theme changes
+#[cfg(feature = "gtk")]
+fn natural_header_size() -> u32 {
+    // The lock isn't held while gtk measures, a theme change notify locks it too.
+    let cached = *NATURAL_HEADER_SIZE
+        .lock()
+        .unwrap_or_else(|err| err.into_inner());
+    if let Some(header_size) = cached {
+        return header_size;
+    }
+
+    if !gtk::is_initialized_main_thread() {
+        warn!("gtk is not initialized, use the default header size");
+
+        return HEADER_SIZE;
+    }
+
+    // The frames already built keep their header size, only the new ones are measured again.
+    GTK_THEME_WATCH_ONCE.call_once(|| {
+        if let Some(settings) = gtk::Settings::default() {
+            settings.connect_gtk_theme_name_notify(|_| invalidate_natural_header_size());
+        }
+    });
+
+    let header_bar = HeaderBar::new();
+    header_bar.set_title(Some("title"));
+    let button = Button::from_icon_name(Some("window-close-symbolic"), IconSize::Menu);
+    button.style_context().add_class("titlebutton");
+    header_bar.pack_end(&button);
+
+    let offscreen_window = OffscreenWindow::new();
+    offscreen_window.add(&header_bar);
+    offscreen_window.show_all();
+
+    let (_, natural_height) = header_bar.preferred_height();
+
+    // gtk keeps the toplevel windows alive until they are destroyed.
+    unsafe { offscreen_window.destroy() }
+
+    let header_size = match u32::try_from(natural_height) {
+        Ok(header_size) if header_size > 0 => header_size,
+        _ => {
+            warn!(
+                natural_height,
+                "invalid natural header bar height, use the default"
+            );
+
+            HEADER_SIZE
+        }
+    };
+
+    debug!(header_size, "measured natural header bar height");
+
+    *NATURAL_HEADER_SIZE
+        .lock()
+        .unwrap_or_else(|err| err.into_inner()) = Some(header_size);
+
+    header_size
+}
+
+/// Forget the measured natural header bar height, such as after the gtk theme changes
+#[cfg(feature = "gtk")]
+fn invalidate_natural_header_size() {
+    *NATURAL_HEADER_SIZE
+        .lock()
+        .unwrap_or_else(|err| err.into_inner()) = None;
+}
+
+/// Init gtk at most once, a failed init fails every later call too
+///
+/// gtk can only be used on the thread it was inited on, so the other threads get an error.
+#[cfg(feature = "gtk")]
+fn init_gtk() -> anyhow::Result<()> {
+    GTK_INIT
+        .get_or_init(|| gtk::init().map_err(|err| err.to_string()))
+        .clone()
+        .map_err(|err| anyhow::anyhow!("gtk init failed: {err}"))?;
+
+    if !gtk::is_initialized_main_thread() {
+        anyhow::bail!("gtk is inited on another thread");
+    }
+
+    Ok(())
+}
+
+/// The button icon size that fits the header bar height in logical pixels
+///
+/// | header height | icon size                          |
+/// |---------------|------------------------------------|
+/// | below 56      | [`IconSize::Menu`], 16px           |
+/// | 56 to 71      | [`IconSize::LargeToolbar`], 24px   |
+/// | 72 and above  | [`IconSize::Dnd`], 32px            |
+///
+/// The natural gtk header bar is below 56 in the common themes, so it keeps the gtk look.
+#[cfg(feature = "gtk")]
+fn icon_size_for_header(header_size: u32) -> IconSize {
+    match header_size {
+        0..=55 => IconSize::Menu,
+        56..=71 => IconSize::LargeToolbar,
+        _ => IconSize::Dnd,
+    }
+}
+
+/// Add the rect to the button damage, a button hovered again before the next draw is only
+/// damaged once
+fn push_damage(button_damage: &mut Vec<(i32, i32, i32, i32)>, rect: (i32, i32, i32, i32)) {
+    if !button_damage.contains(&rect) {
+        button_damage.push(rect);
+    }
+}
+
+/// Whether the pointer location resizes the window
+fn is_resize_edge(location: Location) -> bool {
+    matches!(
+        location,
+        Location::Top
+            | Location::TopRight
+            | Location::Right
+            | Location::BottomRight
+            | Location::Bottom
+            | Location::BottomLeft
+            | Location::Left
+            | Location::TopLeft
+    )
+}
+
+/// Set the tooltip and the accessible name of the button
+#[cfg(feature = "gtk")]
+fn set_button_label(button: &Button, label: &str) {
+    button.set_tooltip_text(Some(label));
+    if let Some(accessible) = button.accessible() {
+        accessible.set_name(label);
+    }
+}
+
+/// The scale the shadow of the surface is rendered at
+///
+/// The shadow is a soft gradient, so with a viewport it is rendered at the logical size and
+/// scaled up by the compositor, which saves most of the pixels on HiDPI.
+fn shadow_scale(shadow_surface: &ShadowSurface, buffer_scale: u32) -> u32 {
+    if shadow_surface.viewport.is_some() {
+        1
+    } else {
+        buffer_scale
+    }
+}
+
+fn decorations_disabled_by_env() -> bool {
+    env::var(NO_DECORATIONS_ENV).is_ok_and(|value| value == "1")
+}
+
+/// Draw a throwaway header bar with the titlebar buttons once, so the icon theme, the css and
+/// the fonts are loaded before the first real draw
+#[cfg(feature = "gtk")]
+fn preload_gtk() {
+    if !gtk::is_initialized_main_thread() {
+        return;
+    }
+
+    GTK_PRELOAD_ONCE.call_once(|| {
+        let header_bar = HeaderBar::new();
+        header_bar.set_title(Some("title"));
+        for (icon_name, class) in [
+            ("window-close-symbolic", "close"),
+            ("window-maximize-symbolic", "maximize"),
+            ("window-minimize-symbolic", "minimize"),
+        ] {
+            let button = Button::from_icon_name(Some(icon_name), IconSize::Menu);
+            let style_context = button.style_context();
+            style_context.add_class("titlebutton");
+            style_context.add_class(class);
+            header_bar.pack_end(&button);
+        }
+
+        let offscreen_window = OffscreenWindow::new();
+        offscreen_window.add(&header_bar);
+        offscreen_window.show_all();
+        pump_gtk_events();
+
+        let (_, width) = offscreen_window.preferred_width();
+        let (_, height) = offscreen_window.preferred_height();
+        match ImageSurface::create(Format::ARgb32, width.max(1), height.max(1))
+            .and_then(Context::new)
+        {
+            Err(err) => warn!(%err, "create the preload surface failed"),
+            Ok(cairo_context) => offscreen_window.draw(&cairo_context),
+        }
+
+        // gtk keeps the toplevel windows alive until they are destroyed.
+        unsafe { offscreen_window.destroy() }
+
+        debug!("preloaded the gtk header bar");
+    });
+}
+
+/// Handle the pending gtk events, so the offscreen widgets are allocated and realized
+///
+/// The loop is bounded and never blocks, the rest of the events are handled on the next draw.
+#[cfg(feature = "gtk")]
+fn pump_gtk_events() {
+    for _ in 0..MAX_GTK_ITERATIONS {
+        if !gtk::events_pending() {
+            return;
+        }
+
+        gtk::main_iteration_do(false);
+    }
+
+    if gtk::events_pending() {
+        warn!(
+            MAX_GTK_ITERATIONS,
+            "gtk still has pending events after the max iterations"
+        );
+    }
+}
+
+#[cfg(feature = "gtk")]
+fn gtk_theme_loaded() -> bool {
+    gtk::Settings::default()
+        .and_then(|settings| settings.gtk_theme_name())
+        .is_some_and(|name| !name.is_empty())
+}
+
+/// Set the direction of the widget and all its descendants, gtk3 doesn't propagate it
+#[cfg(feature = "gtk")]
+fn set_direction_recursive(widget: &impl IsA<Widget>, direction: TextDirection) {
+    widget.set_direction(direction);
+
+    if let Some(container) = widget.dynamic_cast_ref::<Container>() {
+        container.forall(|child| set_direction_recursive(child, direction));
+    }
+}
+
+/// The laid out header bar, ready to be painted
+enum PreparedHeader {
+    #[cfg(feature = "gtk")]
+    Widgets(HeaderWidgets),
+    /// Rendered by the custom renderer
+    Pixmap(Pixmap),
+}
+
+/// Paint the prepared header bar into the `width` x `height` canvas
+fn paint_header(
+    prepared_header: &PreparedHeader,
+    canvas: &mut [u8],
+    width: u32,
+    height: u32,
+    opaque: bool,
+) -> anyhow::Result<()> {
+    let pixmap = match prepared_header {
+        #[cfg(feature = "gtk")]
+        PreparedHeader::Widgets(header_widgets) => {
+            return paint_header_widgets(header_widgets, canvas, width, height, opaque);
+        }
+
+        PreparedHeader::Pixmap(pixmap) => pixmap,
+    };
+
+    let len = check_buffer_size(width, height)? * height as usize;
+    if pixmap.width() != width || pixmap.height() != height || canvas.len() < len {
+        anyhow::bail!("header bar pixmap doesn't match the {width}x{height} canvas");
+    }
+
+    // tiny-skia is premultiplied RGBA, the canvas is premultiplied native endian ARGB.
+    for (src, dst) in pixmap
+        .data()
+        .chunks_exact(4)
+        .zip(canvas.chunks_exact_mut(4))
+    {
+        let argb = u32::from_be_bytes([src[3], src[0], src[1], src[2]]);
+        dst.copy_from_slice(&argb.to_ne_bytes());
+    }
+
+    Ok(())
+}
+
+/// Paint the laid out header bar widgets into the `width` x `height` canvas
+#[cfg(feature = "gtk")]
+fn paint_header_widgets(
+    header_widgets: &HeaderWidgets,
+    canvas: &mut [u8],
+    width: u32,
+    height: u32,
+    opaque: bool,
+) -> anyhow::Result<()> {
+    let stride = check_buffer_size(width, height)?;
+    if canvas.len() < stride * height as usize {
+        anyhow::bail!(
+            "canvas of {} bytes is too small for {width}x{height}",
+            canvas.len()
+        );
+    }
+
+    let cairo_format = if opaque {
+        Format::Rgb24
+    } else {
+        Format::ARgb32
+    };
+
+    // A reused buffer still has the last frame, the rounded corners must be transparent.
+    canvas.fill(0);
+
+    let image_surface = unsafe {
+        ImageSurface::create_for_data_unsafe(
+            canvas.as_mut_ptr() as _,
+            cairo_format,
+            width as _,
+            height as _,
+            stride as _,
+        )?
+    };
+    let cairo_context = Context::new(image_surface)?;
+
+    header_widgets.offscreen_window.draw(&cairo_context);
+
+    Ok(())
+}
+
+/// The visible border rect of the shadow part, in the physical pixels of its buffer
+///
+/// `pos` is the logical position of the shadow surface, `size` is its physical buffer size.
+/// Return `None` when the border doesn't fit, such as for a very short window.
+fn visible_border_rect(
+    shadow_part: ShadowPart,
+    pos: (i32, i32),
+    size: (u32, u32),
+    scale: u32,
+) -> Option<Rect> {
+    // The visible border is one pt.
+    let visible_border_size = VISIBLE_BORDER_SIZE * scale;
+    let x = pos.0.unsigned_abs().saturating_mul(scale);
+    let y = pos.1.unsigned_abs().saturating_mul(scale);
+    let (width, height) = size;
+
+    // XXX we do all the match using integral types and then convert to f32 in the end to
+    // ensure that result is finite.
+    let (x, y, width, height) = match shadow_part {
+        ShadowPart::Left => (
+            x.checked_sub(visible_border_size)?,
+            y,
+            visible_border_size,
+            height.checked_sub(y)?,
+        ),
+
+        ShadowPart::Right => (0, y, visible_border_size, height.checked_sub(y)?),
+
+        // We draw small visible border only bellow the window surface, no need to handle `TOP`.
+        ShadowPart::Bottom => {
+            let x = x.checked_sub(visible_border_size)?;
+
+            (
+                x,
+                0,
+                width.checked_sub(x.saturating_mul(2))?,
+                visible_border_size,
+            )
+        }
+
+        ShadowPart::Top => return None,
+    };
+
+    if width == 0 || height == 0 {
+        return None;
+    }
+
+    Rect::from_xywh(x as f32, y as f32, width as f32, height as f32)
+}
+
+/// Reuse the cached buffer when it has the same size and the compositor released it, otherwise
+/// allocate a new one
+fn cached_buffer<'a, P: BufferPool>(
+    pool: &'a mut P,
+    cached: &'a mut Option<CachedBuffer<P::Buffer>>,
+    width: u32,
+    height: u32,
+    format: wl_shm::Format,
+) -> anyhow::Result<(&'a P::Buffer, &'a mut [u8])> {
+    let stride = check_buffer_size(width, height)?;
+    let spec = BufferSpec {
+        width,
+        height,
+        format,
+    };
+
+    let reusable = can_reuse_buffer(cached.as_ref().map(|cached| cached.spec), spec, || {
+        cached
+            .as_ref()
+            .is_some_and(|cached| pool.canvas(&cached.buffer).is_some())
+    });
+
+    if !reusable {
+        let buffer = pool.create_buffer(spec, stride)?;
+
+        *cached = Some(CachedBuffer { buffer, spec });
+    }
+
+    let cached = cached.as_ref().expect("the buffer is cached above");
+    let canvas = pool
+        .canvas(&cached.buffer)
+        .expect("a new or released buffer should always have a canvas");
+
+    Ok((&cached.buffer, canvas))
+}
+
+/// Whether the cached buffer has the requested size and format and the compositor released it,
+/// `released` is only checked for a matching buffer
+fn can_reuse_buffer(
+    cached: Option<BufferSpec>,
+    requested: BufferSpec,
+    released: impl FnOnce() -> bool,
+) -> bool {
+    cached == Some(requested) && released()
+}
+
+/// Check the buffer size is drawable and return the stride of its 4 bytes pixels
+///
+/// cairo can't create a larger image surface, and it keeps a pathological size or scale from
+/// overflowing the stride.
+fn check_buffer_size(width: u32, height: u32) -> anyhow::Result<usize> {
+    if width > MAX_BUFFER_SIZE || height > MAX_BUFFER_SIZE {
+        anyhow::bail!("buffer size {width}x{height} is larger than the max size {MAX_BUFFER_SIZE}");
+    }
+
+    Ok(width as usize * 4)
+}
+
+/// The buffer size of the logical size at the scale, it saturates at `u32::MAX`
+fn physical_size(logical: u32, scale: f64) -> u32 {
+    (logical as f64 * scale).round() as u32
+}
+
+fn button_states(button_kinds: Vec<ButtonKind>) -> Vec<ButtonState> {
+    button_kinds.into_iter().map(ButtonState::new).collect()
+}
+
+fn init_shadow_surfaces_pos(
+    shadow_surfaces: &mut [ShadowSurface; 4],
+    header_size: u32,
+    border_size: u32,
+) {
     // top
     let surface = &mut shadow_surfaces[0];
-    surface.x = -(BORDER_SIZE as i32);
-    surface.y = -(HEADER_SIZE as i32 + BORDER_SIZE as i32);
-    surface.height = BORDER_SIZE;
+    surface.x = -(border_size as i32);
+    surface.y = -(header_size as i32 + border_size as i32);
+    surface.height = border_size;
 
     // left
     let surface = &mut shadow_surfaces[1];
-    surface.x = -(BORDER_SIZE as i32);
-    surface.y = -(HEADER_SIZE as i32);
-    surface.width = BORDER_SIZE;
+    surface.x = -(border_size as i32);
+    surface.y = -(header_size as i32);
+    surface.width = border_size;
 
     // right
     let surface = &mut shadow_surfaces[2];
-    surface.y = -(HEADER_SIZE as i32);
-    surface.width = BORDER_SIZE;
+    surface.y = -(header_size as i32);
+    surface.width = border_size;
 
     // bottom
     let surface = &mut shadow_surfaces[3];
-    surface.x = -(BORDER_SIZE as i32);
-    surface.height = BORDER_SIZE;
+    surface.x = -(border_size as i32);
+    surface.height = border_size;
+}
+
+/// The resize handle of the shadow surface in surface local coordinates, it is a band along the
+/// window, the top and bottom bands also cover the corners
+fn shadow_input_rect(
+    shadow_surface: &ShadowSurface,
+    part: ShadowPart,
+    border_size: u32,
+) -> (i32, i32, i32, i32) {
+    let handle = RESIZE_HANDLE_SIZE.min(border_size);
+    let gap = border_size - handle;
+    let (width, height) = (shadow_surface.width, shadow_surface.height);
+
+    let (x, y, width, height) = match part {
+        ShadowPart::Top => (gap, gap, width.saturating_sub(2 * gap), handle),
+        ShadowPart::Left => (gap, 0, handle, height),
+        ShadowPart::Right => (0, 0, handle, height),
+        ShadowPart::Bottom => (gap, 0, width.saturating_sub(2 * gap), handle),
+    };
+
+    (
+        x as i32,
+        y as i32,
+        width.min(i32::MAX as u32) as i32,
+        height.min(i32::MAX as u32) as i32,
+    )
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
@@ -831,3 +3163,152 @@ enum CursorArea {
     RightShadow,
     Window,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn physical_size_saturates() {
+        assert_eq!(physical_size(100, 1.5), 150);
+        assert_eq!(physical_size(u32::MAX / 2, 64.0), u32::MAX);
+    }
+
+    #[test]
+    fn check_buffer_size_rejects_huge_buffers() {
+        assert_eq!(check_buffer_size(1920, 50).unwrap(), 1920 * 4);
+        assert!(check_buffer_size(physical_size(u32::MAX / 2, 64.0), 50).is_err());
+        assert!(check_buffer_size(1920, MAX_BUFFER_SIZE + 1).is_err());
+    }
+
+    #[test]
+    fn in_button_scales_the_button_rect() {
+        let state = ButtonState {
+            x: 200,
+            y: 20,
+            width: 60,
+            height: 60,
+            ..ButtonState::new(ButtonKind::Close)
+        };
+
+        assert!(GtkFrame::in_button((110.0, 20.0), &state, 2.0));
+        assert!(!GtkFrame::in_button((210.0, 40.0), &state, 2.0));
+        assert!(GtkFrame::in_button((210.0, 40.0), &state, 1.0));
+    }
+
+    #[test]
+    fn cached_buffer_is_reused_only_with_the_same_spec() {
+        let spec = BufferSpec {
+            width: 1920,
+            height: 50,
+            format: wl_shm::Format::Argb8888,
+        };
+        let wider = BufferSpec {
+            width: 1921,
+            ..spec
+        };
+        let opaque = BufferSpec {
+            format: wl_shm::Format::Xrgb8888,
+            ..spec
+        };
+
+        assert!(can_reuse_buffer(Some(spec), spec, || true));
+        assert!(!can_reuse_buffer(Some(spec), spec, || false));
+        assert!(!can_reuse_buffer(None, spec, || true));
+        assert!(!can_reuse_buffer(Some(spec), wider, || unreachable!()));
+        assert!(!can_reuse_buffer(Some(spec), opaque, || unreachable!()));
+    }
+
+    /// A pool with a single canvas which counts the allocations
+    #[derive(Default)]
+    struct CountingPool {
+        created: usize,
+        /// The compositor holds the last buffer
+        busy: bool,
+        canvas: Vec<u8>,
+    }
+
+    impl BufferPool for CountingPool {
+        type Buffer = usize;
+
+        fn create_buffer(&mut self, spec: BufferSpec, stride: usize) -> anyhow::Result<usize> {
+            self.created += 1;
+            self.busy = false;
+            self.canvas = vec![0; stride * spec.height as usize];
+
+            Ok(self.created)
+        }
+
+        fn canvas(&mut self, _buffer: &usize) -> Option<&mut [u8]> {
+            (!self.busy).then_some(self.canvas.as_mut_slice())
+        }
+    }
+
+    #[test]
+    fn cached_buffer_allocates_only_on_changes() {
+        let mut pool = CountingPool::default();
+        let mut cached = None;
+
+        for _ in 0..10 {
+            let (_, canvas) =
+                cached_buffer(&mut pool, &mut cached, 100, 50, wl_shm::Format::Argb8888).unwrap();
+            assert_eq!(canvas.len(), 100 * 50 * 4);
+        }
+        assert_eq!(pool.created, 1);
+
+        cached_buffer(&mut pool, &mut cached, 120, 50, wl_shm::Format::Argb8888).unwrap();
+        assert_eq!(pool.created, 2);
+
+        cached_buffer(&mut pool, &mut cached, 120, 50, wl_shm::Format::Xrgb8888).unwrap();
+        assert_eq!(pool.created, 3);
+
+        // A buffer the compositor still holds can't be drawn again.
+        pool.busy = true;
+        cached_buffer(&mut pool, &mut cached, 120, 50, wl_shm::Format::Xrgb8888).unwrap();
+        assert_eq!(pool.created, 4);
+    }
+
+    #[test]
+    fn visible_border_rect_is_in_physical_pixels() {
+        let pos = (-(BORDER_SIZE as i32), -(HEADER_SIZE as i32));
+        let rect = visible_border_rect(ShadowPart::Left, pos, (88, 400), 2).unwrap();
+        assert_eq!(
+            (rect.x(), rect.y(), rect.width(), rect.height()),
+            (86.0, 100.0, 2.0, 300.0)
+        );
+
+        // A window shorter than the header bar has no side border left.
+        assert!(visible_border_rect(ShadowPart::Left, pos, (88, 80), 2).is_none());
+        assert!(visible_border_rect(ShadowPart::Right, (0, pos.1), (88, 100), 2).is_none());
+
+        // A bottom shadow narrower than its borders.
+        let pos = (-(BORDER_SIZE as i32), 0);
+        assert!(visible_border_rect(ShadowPart::Bottom, pos, (100, 88), 2).is_none());
+        assert!(visible_border_rect(ShadowPart::Top, pos, (400, 88), 1).is_none());
+    }
+
+    #[cfg(feature = "gtk")]
+    #[test]
+    fn compact_header_keeps_menu_icons() {
+        assert_eq!(icon_size_for_header(COMPACT_HEADER_SIZE), IconSize::Menu);
+    }
+
+    #[test]
+    fn push_damage_coalesces_rects() {
+        let mut button_damage = Vec::new();
+        push_damage(&mut button_damage, (0, 0, 10, 10));
+        push_damage(&mut button_damage, (10, 0, 10, 10));
+        push_damage(&mut button_damage, (0, 0, 10, 10));
+
+        assert_eq!(button_damage, [(0, 0, 10, 10), (10, 0, 10, 10)]);
+    }
+
+    #[cfg(feature = "gtk")]
+    #[test]
+    fn icon_size_follows_header_height() {
+        assert_eq!(icon_size_for_header(HEADER_SIZE), IconSize::Menu);
+        assert_eq!(icon_size_for_header(56), IconSize::LargeToolbar);
+        assert_eq!(icon_size_for_header(71), IconSize::LargeToolbar);
+        assert_eq!(icon_size_for_header(72), IconSize::Dnd);
+    }
+}
@@ -4,17 +4,25 @@ use smithay_client_toolkit::reexports::csd_frame::{
     CursorIcon, FrameAction, ResizeEdge, WindowManagerCapabilities, WindowState,
 };
 
+use crate::config::TitlebarAction;
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum ButtonKind {
     Close,
     Maximize,
     Minimize,
+    /// Opens the window menu
+    Menu,
+    /// A gap between buttons, it can't be clicked
+    Spacer,
+    /// The application icon, clicking it opens the window menu like gtk
+    Icon,
 }
 
-/// Time to register the next click as a double click.
+/// Default time to register the next click as a double click.
 ///
 /// The value is the same as the default in gtk4.
-const DOUBLE_CLICK_DURATION: Duration = Duration::from_millis(400);
+pub(crate) const DOUBLE_CLICK_DURATION: Duration = Duration::from_millis(400);
 
 /// The state of the mouse input inside the decorations frame.
 #[derive(Debug, Default)]
@@ -24,10 +32,25 @@ pub(crate) struct MouseState {
     /// The surface local location inside the surface.
     pub cursor_pos: Option<(f64, f64)>,
 
-    pub button_pressed: bool,
+    /// The touch point inside the decorations frame, it is tracked apart from the pointer so
+    /// they don't clobber each other.
+    pub touch: Option<TouchPoint>,
 
     /// The instant of the last click.
     last_normal_click: Option<Duration>,
+
+    /// The header bar height, the header bar surface is placed above the window by it.
+    pub header_size: u32,
+}
+
+/// A touch point inside the decorations frame.
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct TouchPoint {
+    pub id: i32,
+    pub location: Location,
+
+    /// The surface local location inside the surface.
+    pub pos: (f64, f64),
 }
 
 impl MouseState {
@@ -35,6 +58,87 @@ impl MouseState {
     pub fn click(
         &mut self,
         timestamp: Duration,
+        double_click_duration: Duration,
+        pressed: bool,
+        resizable: bool,
+        state: &WindowState,
+        wm_capabilities: &WindowManagerCapabilities,
+    ) -> Option<FrameAction> {
+        self.press(
+            self.location,
+            self.cursor_pos,
+            timestamp,
+            double_click_duration,
+            pressed,
+            resizable,
+            state,
+            wm_capabilities,
+        )
+    }
+
+    /// A touch point went down on the decorations frame, it acts like a normal click.
+    #[allow(clippy::too_many_arguments)]
+    pub fn touch_down(
+        &mut self,
+        touch: TouchPoint,
+        timestamp: Duration,
+        double_click_duration: Duration,
+        resizable: bool,
+        state: &WindowState,
+        wm_capabilities: &WindowManagerCapabilities,
+    ) -> Option<FrameAction> {
+        self.touch = Some(touch);
+
+        self.press(
+            touch.location,
+            Some(touch.pos),
+            timestamp,
+            double_click_duration,
+            true,
+            resizable,
+            state,
+            wm_capabilities,
+        )
+    }
+
+    /// A touch point went up, the release happens where the touch point went down.
+    #[allow(clippy::too_many_arguments)]
+    pub fn touch_up(
+        &mut self,
+        id: i32,
+        timestamp: Duration,
+        double_click_duration: Duration,
+        resizable: bool,
+        state: &WindowState,
+        wm_capabilities: &WindowManagerCapabilities,
+    ) -> Option<FrameAction> {
+        let touch = self.touch.filter(|touch| touch.id == id)?;
+        self.touch = None;
+
+        self.press(
+            touch.location,
+            Some(touch.pos),
+            timestamp,
+            double_click_duration,
+            false,
+            resizable,
+            state,
+            wm_capabilities,
+        )
+    }
+
+    /// The touch sequence was cancelled by the compositor.
+    pub fn touch_cancel(&mut self) {
+        self.touch = None;
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn press(
+        &mut self,
+        location: Location,
+        pos: Option<(f64, f64)>,
+        timestamp: Duration,
+        double_click_duration: Duration,
         pressed: bool,
         resizable: bool,
         state: &WindowState,
@@ -42,7 +146,7 @@ impl MouseState {
     ) -> Option<FrameAction> {
         let maximized = state.contains(WindowState::MAXIMIZED);
 
-        let action = match self.location {
+        let action = match location {
             Location::Top if resizable => FrameAction::Resize(ResizeEdge::Top),
             Location::TopLeft if resizable => FrameAction::Resize(ResizeEdge::TopLeft),
             Location::Left if resizable => FrameAction::Resize(ResizeEdge::Left),
@@ -52,43 +156,53 @@ impl MouseState {
             Location::Right if resizable => FrameAction::Resize(ResizeEdge::Right),
             Location::TopRight if resizable => FrameAction::Resize(ResizeEdge::TopRight),
 
-            Location::Button(button_kind) => {
-                self.button_pressed = pressed;
-
-                match button_kind {
-                    ButtonKind::Close => {
-                        if !pressed {
-                            FrameAction::Close
-                        } else {
-                            return None;
-                        }
+            Location::Button(button_kind) => match button_kind {
+                ButtonKind::Close => {
+                    if !pressed {
+                        FrameAction::Close
+                    } else {
+                        return None;
                     }
-                    ButtonKind::Maximize => {
-                        if !pressed {
-                            if maximized {
-                                FrameAction::UnMaximize
-                            } else {
-                                FrameAction::Maximize
-                            }
+                }
+                ButtonKind::Maximize => {
+                    if !pressed {
+                        if maximized {
+                            FrameAction::UnMaximize
                         } else {
-                            return None;
+                            FrameAction::Maximize
                         }
+                    } else {
+                        return None;
                     }
-                    ButtonKind::Minimize => {
-                        if !pressed {
-                            FrameAction::Minimize
-                        } else {
-                            return None;
-                        }
+                }
+                ButtonKind::Minimize => {
+                    if !pressed {
+                        FrameAction::Minimize
+                    } else {
+                        return None;
                     }
                 }
-            }
+                ButtonKind::Menu | ButtonKind::Icon => {
+                    if !pressed && wm_capabilities.contains(WindowManagerCapabilities::WINDOW_MENU)
+                    {
+                        let (x, y) = menu_position(pos?, self.header_size);
+
+                        FrameAction::ShowMenu(x, y)
+                    } else {
+                        return None;
+                    }
+                }
+                ButtonKind::Spacer => return None,
+            },
 
+            // A fixed size window can't be maximized by a double click, it only moves.
             Location::Head
-                if pressed && wm_capabilities.contains(WindowManagerCapabilities::MAXIMIZE) =>
+                if pressed
+                    && resizable
+                    && wm_capabilities.contains(WindowManagerCapabilities::MAXIMIZE) =>
             {
                 match self.last_normal_click.replace(timestamp) {
-                    Some(last) if timestamp.saturating_sub(last) < DOUBLE_CLICK_DURATION => {
+                    Some(last) if timestamp.saturating_sub(last) < double_click_duration => {
                         if maximized {
                             FrameAction::UnMaximize
                         } else {
@@ -107,28 +221,37 @@ impl MouseState {
         Some(action)
     }
 
-    /// Alternative click on decorations frame was made.
-    pub fn alternate_click(
+    /// A non primary click on decorations frame was made, such as the alternate or middle click.
+    pub fn secondary_click(
         &mut self,
+        action: TitlebarAction,
         pressed: bool,
         wm_capabilities: &WindowManagerCapabilities,
     ) -> Option<FrameAction> {
         // Invalidate the normal click.
         self.last_normal_click = None;
 
-        match self.location {
-            Location::Head | Location::Button(_)
-                if pressed && wm_capabilities.contains(WindowManagerCapabilities::WINDOW_MENU) =>
+        if !pressed || !matches!(self.location, Location::Head | Location::Button(_)) {
+            return None;
+        }
+
+        match action {
+            TitlebarAction::None => None,
+
+            TitlebarAction::Move => Some(FrameAction::Move),
+
+            TitlebarAction::Minimize
+                if wm_capabilities.contains(WindowManagerCapabilities::MINIMIZE) =>
+            {
+                Some(FrameAction::Minimize)
+            }
+
+            TitlebarAction::Menu
+                if wm_capabilities.contains(WindowManagerCapabilities::WINDOW_MENU) =>
             {
-                self.cursor_pos.map(|pos| {
-                    FrameAction::ShowMenu(
-                        // XXX this could be one 1pt off when the frame is not maximized, but it's not
-                        // like it really matters in the end.
-                        pos.0 as _,
-                        // We must offset it by header size for precise position.
-                        pos.1 as _,
-                    )
-                })
+                let (x, y) = self.window_menu_position()?;
+
+                Some(FrameAction::ShowMenu(x, y))
             }
 
             _ => None,
@@ -164,24 +287,48 @@ impl MouseState {
         }
     }
 
+    /// Where the window menu opens for the pointer on the header bar, in window surface
+    /// coordinates, see [`menu_position`].
+    pub fn window_menu_position(&self) -> Option<(i32, i32)> {
+        if !matches!(self.location, Location::Head | Location::Button(_)) {
+            return None;
+        }
+
+        self.cursor_pos
+            .map(|pos| menu_position(pos, self.header_size))
+    }
+
     /// The mouse left the decorations frame.
     pub fn left(&mut self) {
-        // Reset only the location.
         self.location = Location::None;
+        // A stale position would keep the last button hovered.
+        self.cursor_pos = None;
     }
 
+    /// The button under the pointer, `None` when the pointer is not in the frame.
+    pub fn hovered_button(&self) -> Option<ButtonKind> {
+        match self.location {
+            Location::Button(kind) if self.cursor_pos.is_some() => Some(kind),
+            _ => None,
+        }
+    }
+
+    /// Whether the mouse is on any part of the decorations frame, the header bar, a button or a
+    /// resize edge. It is false over the client area.
     pub fn in_frame(&self) -> bool {
-        matches!(
-            self.location,
-            Location::Head
-                | Location::TopLeft
-                | Location::TopRight
-                | Location::Top
-                | Location::Button(_)
-        )
+        self.location != Location::None
     }
 }
 
+/// Convert a position on the header bar surface to the window surface coordinates of
+/// [`FrameAction::ShowMenu`].
+///
+/// The header bar surface is placed at `(0, -header_size)` of the window surface, so only y
+/// needs the offset, a position on the header bar has a negative y.
+pub(crate) fn menu_position(pos: (f64, f64), header_size: u32) -> (i32, i32) {
+    (pos.0 as i32, pos.1 as i32 - header_size as i32)
+}
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
 pub enum Location {
     #[default]
@@ -197,3 +344,78 @@ pub enum Location {
     TopLeft,
     Button(ButtonKind),
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn double_click(resizable: bool) -> Option<FrameAction> {
+        let mut mouse = MouseState {
+            location: Location::Head,
+            ..Default::default()
+        };
+
+        let mut click = |timestamp| {
+            mouse.click(
+                timestamp,
+                DOUBLE_CLICK_DURATION,
+                true,
+                resizable,
+                &WindowState::empty(),
+                &WindowManagerCapabilities::all(),
+            )
+        };
+
+        assert_eq!(click(Duration::ZERO), Some(FrameAction::Move));
+
+        click(Duration::from_millis(100))
+    }
+
+    #[test]
+    fn menu_position_is_above_the_window() {
+        assert_eq!(menu_position((10.5, 20.0), 50), (10, -30));
+        assert_eq!(menu_position((0.0, 0.0), 50), (0, -50));
+    }
+
+    #[test]
+    fn window_menu_position_only_on_header() {
+        let mut mouse = MouseState {
+            header_size: 50,
+            ..Default::default()
+        };
+        mouse.moved(Location::Head, 10.0, 20.0, true, WindowState::empty());
+        assert_eq!(mouse.window_menu_position(), Some((10, -30)));
+
+        mouse.moved(Location::Left, 0.0, 20.0, true, WindowState::empty());
+        assert_eq!(mouse.window_menu_position(), None);
+
+        mouse.left();
+        assert_eq!(mouse.window_menu_position(), None);
+    }
+
+    #[test]
+    fn no_hovered_button_after_leave() {
+        let mut mouse = MouseState::default();
+        mouse.moved(
+            Location::Button(ButtonKind::Close),
+            10.0,
+            10.0,
+            true,
+            WindowState::empty(),
+        );
+        assert_eq!(mouse.hovered_button(), Some(ButtonKind::Close));
+
+        mouse.left();
+        assert_eq!(mouse.hovered_button(), None);
+    }
+
+    #[test]
+    fn double_click_maximizes_resizable_window() {
+        assert_eq!(double_click(true), Some(FrameAction::Maximize));
+    }
+
+    #[test]
+    fn double_click_moves_fixed_size_window() {
+        assert_eq!(double_click(false), Some(FrameAction::Move));
+    }
+}
@@ -0,0 +1,110 @@
+use std::sync::{mpsc, OnceLock};
+use std::thread;
+use std::time::Duration;
+
+use tracing::warn;
+use zbus::blocking::{Connection, Proxy};
+use zbus::zvariant::{OwnedValue, Value};
+
+const PORTAL_DESTINATION: &str = "org.freedesktop.portal.Desktop";
+const PORTAL_PATH: &str = "/org/freedesktop/portal/desktop";
+const PORTAL_SETTINGS_INTERFACE: &str = "org.freedesktop.portal.Settings";
+
+/// How long a setting read may block the caller, such as the frame build
+const READ_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// The session bus connection shared by all the reads and watchers
+static SESSION: OnceLock<Result<Connection, String>> = OnceLock::new();
+
+fn session() -> anyhow::Result<&'static Connection> {
+    SESSION
+        .get_or_init(|| Connection::session().map_err(|err| err.to_string()))
+        .as_ref()
+        .map_err(|err| anyhow::anyhow!("connect to the session bus failed: {err}"))
+}
+
+/// Read a setting from the XDG Settings Portal, the value is unwrapped from the variant `Read`
+/// returns
+///
+/// Return an error when the portal isn't reachable, doesn't reply in time or the setting doesn't
+/// exist.
+pub fn read_setting(namespace: &str, key: &str) -> anyhow::Result<OwnedValue> {
+    let (sender, receiver) = mpsc::sync_channel(1);
+    let (thread_namespace, thread_key) = (namespace.to_string(), key.to_string());
+
+    // A slow portal keeps the read thread, the caller falls back after the timeout.
+    thread::Builder::new()
+        .name("sctk_gtk portal read".to_string())
+        .spawn(move || {
+            let _ = sender.send(call_read(&thread_namespace, &thread_key));
+        })?;
+
+    receiver
+        .recv_timeout(READ_TIMEOUT)
+        .map_err(|_| anyhow::anyhow!("read setting {namespace}.{key} timed out"))?
+}
+
+fn call_read(namespace: &str, key: &str) -> anyhow::Result<OwnedValue> {
+    let reply = session()?.call_method(
+        Some(PORTAL_DESTINATION),
+        PORTAL_PATH,
+        Some(PORTAL_SETTINGS_INTERFACE),
+        "Read",
+        &(namespace, key),
+    )?;
+
+    let value: OwnedValue = reply.body().deserialize()?;
+
+    // `Read` wraps the value in one more variant
+    if let Value::Value(value) = &*value {
+        return Ok(value.try_to_owned()?);
+    }
+
+    Ok(value)
+}
+
+/// Read a string setting from the XDG Settings Portal
+pub fn read_string_setting(namespace: &str, key: &str) -> anyhow::Result<String> {
+    let value = read_setting(namespace, key)?;
+
+    match &*value {
+        Value::Str(value) => Ok(value.to_string()),
+        other => Err(anyhow::anyhow!(
+            "setting {namespace}.{key} is not a string: {other:?}"
+        )),
+    }
+}
+
+/// Call `on_change` every time the setting changes, this blocks until the portal connection is
+/// closed
+pub fn watch_setting(
+    namespace: &str,
+    key: &str,
+    mut on_change: impl FnMut(),
+) -> anyhow::Result<()> {
+    let proxy = Proxy::new(
+        session()?,
+        PORTAL_DESTINATION,
+        PORTAL_PATH,
+        PORTAL_SETTINGS_INTERFACE,
+    )?;
+
+    for message in proxy.receive_signal("SettingChanged")? {
+        let (changed_namespace, changed_key, _value): (String, String, OwnedValue) =
+            match message.body().deserialize() {
+                Err(err) => {
+                    warn!(%err, "deserialize SettingChanged signal failed");
+
+                    continue;
+                }
+
+                Ok(body) => body,
+            };
+
+        if changed_namespace == namespace && changed_key == key {
+            on_change();
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,47 @@
+use smithay_client_toolkit::reexports::csd_frame::{WindowManagerCapabilities, WindowState};
+use tiny_skia::PixmapMut;
+
+use crate::color_scheme::ColorScheme;
+use crate::pointer::ButtonKind;
+use crate::style::HeaderTheme;
+
+/// Draws the header bar instead of gtk, see
+/// [`GtkFrame::set_header_renderer`](crate::GtkFrame::set_header_renderer)
+pub trait HeaderRenderer {
+    /// Draw the header bar into the pixmap and return the rects of the drawn buttons
+    ///
+    /// The pixmap has the physical size of the header bar and is cleared to transparent. A
+    /// button without a rect can't be clicked.
+    fn render(&mut self, context: &HeaderContext, pixmap: &mut PixmapMut) -> Vec<ButtonRect>;
+}
+
+/// What the header bar shows
+#[derive(Debug, Clone)]
+pub struct HeaderContext<'a> {
+    /// The scale from logical to physical pixels
+    pub scale: f64,
+    /// The title, with the title prefix
+    pub title: &'a str,
+    pub subtitle: Option<&'a str>,
+    /// The buttons at the start of the header bar, in `pack_start` order
+    pub left_buttons: &'a [ButtonKind],
+    /// The buttons at the end of the header bar, in `pack_end` order
+    pub right_buttons: &'a [ButtonKind],
+    pub hovered_button: Option<ButtonKind>,
+    pub pressed_button: Option<ButtonKind>,
+    pub state: WindowState,
+    pub wm_capabilities: WindowManagerCapabilities,
+    pub color_scheme: ColorScheme,
+    /// The colors set on the frame, every `None` color is up to the renderer
+    pub header_theme: HeaderTheme,
+}
+
+/// The rect of a header bar button in physical pixels
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct ButtonRect {
+    pub kind: ButtonKind,
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
@@ -1,10 +1,16 @@
 use std::collections::BTreeMap;
-use std::process::Command;
+use std::mem;
 
 use smithay_client_toolkit::reexports::client::protocol::wl_subsurface::WlSubsurface;
 use smithay_client_toolkit::reexports::client::protocol::wl_surface::WlSurface;
-use tiny_skia::{Color, Paint, Pixmap, PixmapMut, PixmapRef, Point, PremultipliedColorU8, Shader};
-use tracing::warn;
+use smithay_client_toolkit::reexports::csd_frame::WindowState;
+use smithay_client_toolkit::reexports::protocols::wp::viewporter::client::wp_viewport::WpViewport;
+use tiny_skia::{
+    Color, ColorU8, Paint, Pixmap, PixmapMut, PixmapRef, Point, PremultipliedColorU8, Shader,
+};
+
+use crate::color_scheme::ColorScheme;
+use crate::pointer::Location;
 
 // These values were generated from a screenshot of an libadwaita window using a script.
 // For more details see: https://github.com/PolyMeilex/sctk-adwaita/pull/43
@@ -12,6 +18,8 @@ pub const SHADOW_SIZE: u32 = 43;
 const SHADOW_PARAMS_ACTIVE: (f32, f32, f32) = (0.206_505_5, 0.104_617_53, -0.000_542_446_2);
 const SHADOW_PARAMS_INACTIVE: (f32, f32, f32) = (0.168_297_29, 0.204_299_8, 0.001_769_798_6);
 const CORNER_RADIUS: u32 = 10;
+/// The largest logical corner radius, a rounder corner would outgrow the shadow around it
+const MAX_CORNER_RADIUS: u32 = SHADOW_SIZE;
 const VISIBLE_BORDER_SIZE: u32 = 1;
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
@@ -31,37 +39,103 @@ impl ShadowPart {
             ShadowPart::Bottom => 3,
         }
     }
+
+    /// The tiled state of the window edge the part is drawn along
+    pub fn tiled_state(&self) -> WindowState {
+        match self {
+            ShadowPart::Top => WindowState::TILED_TOP,
+            ShadowPart::Left => WindowState::TILED_LEFT,
+            ShadowPart::Right => WindowState::TILED_RIGHT,
+            ShadowPart::Bottom => WindowState::TILED_BOTTOM,
+        }
+    }
+
+    /// Whether the part draws the visible border of the resize edge, the top edge has no
+    /// visible border since the header bar covers it
+    pub fn has_border_of(&self, edge: Location) -> bool {
+        match self {
+            ShadowPart::Top => false,
+            ShadowPart::Left => matches!(
+                edge,
+                Location::Left | Location::TopLeft | Location::BottomLeft
+            ),
+            ShadowPart::Right => matches!(
+                edge,
+                Location::Right | Location::TopRight | Location::BottomRight
+            ),
+            ShadowPart::Bottom => matches!(
+                edge,
+                Location::Bottom | Location::BottomLeft | Location::BottomRight
+            ),
+        }
+    }
 }
 
-fn shadow(pixel_dist: f32, scale: u32, active: bool) -> f32 {
-    let (a, b, c) = if active {
-        SHADOW_PARAMS_ACTIVE
-    } else {
-        SHADOW_PARAMS_INACTIVE
-    };
+/// The shadow look, the alpha at the logical distance `d` from the window is
+/// `opacity * exp(-falloff * d) + offset`
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) struct ShadowParams {
+    color: ColorU8,
+    opacity: f32,
+    falloff: f32,
+    offset: f32,
+    /// The logical radius of the rounded window corners
+    corner_radius: u32,
+}
 
-    a * (-b * (pixel_dist / scale as f32)).exp() + c
+impl ShadowParams {
+    /// The key of the rendered shadow cache
+    fn key(&self) -> (u32, u32, u32, u32, u32) {
+        let color = u32::from_be_bytes([
+            self.color.red(),
+            self.color.green(),
+            self.color.blue(),
+            self.color.alpha(),
+        ]);
+
+        (
+            color,
+            self.opacity.to_bits(),
+            self.falloff.to_bits(),
+            self.offset.to_bits(),
+            self.corner_radius,
+        )
+    }
+
+    fn color(&self, alpha: f32) -> PremultipliedColorU8 {
+        let alpha = (alpha.clamp(0.0, 1.0) * u8::MAX as f32).round() as u8;
+
+        ColorU8::from_rgba(
+            self.color.red(),
+            self.color.green(),
+            self.color.blue(),
+            alpha,
+        )
+        .premultiply()
+    }
+}
+
+fn shadow(pixel_dist: f32, scale: u32, params: &ShadowParams) -> f32 {
+    params.opacity * (-params.falloff * (pixel_dist / scale as f32)).exp() + params.offset
 }
 
 #[derive(Debug)]
 struct RenderedShadow {
     side: Pixmap,
     edges: Pixmap,
+    /// The physical corner radius the edges are rendered with
+    corner_radius: u32,
 }
 
 impl RenderedShadow {
-    fn new(scale: u32, active: bool) -> RenderedShadow {
-        let shadow_size = SHADOW_SIZE * scale;
-        let corner_radius = CORNER_RADIUS * scale;
+    fn new(shadow_size: u32, scale: u32, params: &ShadowParams) -> RenderedShadow {
+        let shadow_size = shadow_size * scale;
+        let corner_radius = params.corner_radius * scale;
 
         #[allow(clippy::unwrap_used)]
         let mut side = Pixmap::new(shadow_size, 1).unwrap();
         for x in 0..side.width() as usize {
-            let alpha = (shadow(x as f32 + 0.5, scale, active) * u8::MAX as f32).round() as u8;
-
-            #[allow(clippy::unwrap_used)]
-            let color = PremultipliedColorU8::from_rgba(0, 0, 0, alpha).unwrap();
-            side.pixels_mut()[x] = color;
+            side.pixels_mut()[x] = params.color(shadow(x as f32 + 0.5, scale, params));
         }
 
         let edges_size = (corner_radius + shadow_size) * 2;
@@ -73,15 +147,16 @@ impl RenderedShadow {
             for x in 0..edges_size as usize {
                 let dist = edges_middle.distance(Point::from_xy(x as f32 + 0.5, y_pos))
                     - corner_radius as f32;
-                let alpha = (shadow(dist, scale, active) * u8::MAX as f32).round() as u8;
-
-                #[allow(clippy::unwrap_used)]
-                let color = PremultipliedColorU8::from_rgba(0, 0, 0, alpha).unwrap();
-                edges.pixels_mut()[y * edges_size as usize + x] = color;
+                edges.pixels_mut()[y * edges_size as usize + x] =
+                    params.color(shadow(dist, scale, params));
             }
         }
 
-        RenderedShadow { side, edges }
+        RenderedShadow {
+            side,
+            edges,
+            corner_radius,
+        }
     }
 
     fn side_draw(
@@ -171,11 +246,11 @@ impl RenderedShadow {
         }
     }
 
-    fn draw(&self, dst_pixmap: &mut PixmapMut, scale: u32, part: ShadowPart) {
-        let shadow_size = (SHADOW_SIZE * scale) as usize;
+    fn draw(&self, dst_pixmap: &mut PixmapMut, shadow_size: u32, scale: u32, part: ShadowPart) {
+        let shadow_size = (shadow_size * scale) as usize;
         let visible_border_size = (VISIBLE_BORDER_SIZE * scale) as usize;
-        let corner_radius = (CORNER_RADIUS * scale) as usize;
-        assert!(corner_radius > visible_border_size);
+        let corner_radius = self.corner_radius as usize;
+        assert!(corner_radius >= visible_border_size);
 
         let dst_width = dst_pixmap.width() as usize;
         let dst_height = dst_pixmap.height() as usize;
@@ -348,34 +423,44 @@ impl RenderedShadow {
 #[derive(Debug)]
 struct CachedPart {
     pixmap: Pixmap,
+    shadow_size: u32,
     scale: u32,
-    active: bool,
+    params: ShadowParams,
 }
 
 impl CachedPart {
     fn new(
         dst_pixmap: &PixmapRef,
         rendered: &RenderedShadow,
+        shadow_size: u32,
         scale: u32,
-        active: bool,
+        params: ShadowParams,
         part: ShadowPart,
     ) -> CachedPart {
         #[allow(clippy::unwrap_used)]
         let mut pixmap = Pixmap::new(dst_pixmap.width(), dst_pixmap.height()).unwrap();
-        rendered.draw(&mut pixmap.as_mut(), scale, part);
+        rendered.draw(&mut pixmap.as_mut(), shadow_size, scale, part);
 
         CachedPart {
             pixmap,
+            shadow_size,
             scale,
-            active,
+            params,
         }
     }
 
-    fn matches(&self, dst_pixmap: &PixmapRef, dst_scale: u32, dst_active: bool) -> bool {
+    fn matches(
+        &self,
+        dst_pixmap: &PixmapRef,
+        dst_shadow_size: u32,
+        dst_scale: u32,
+        dst_params: &ShadowParams,
+    ) -> bool {
         self.pixmap.width() == dst_pixmap.width()
             && self.pixmap.height() == dst_pixmap.height()
+            && self.shadow_size == dst_shadow_size
             && self.scale == dst_scale
-            && self.active == dst_active
+            && self.params == *dst_params
     }
 
     fn draw(&self, dst_pixmap: &mut PixmapMut) {
@@ -386,32 +471,52 @@ impl CachedPart {
 
 #[derive(Default, Debug)]
 pub struct Shadow {
-    part_cache: [Option<CachedPart>; 5],
-    // (scale, active) -> RenderedShadow
-    rendered: BTreeMap<(u32, bool), RenderedShadow>,
+    // The two most recent looks of every part, so toggling the active state doesn't re-render
+    part_cache: [[Option<CachedPart>; 2]; 5],
+    // (shadow_size, scale, params key) -> RenderedShadow
+    rendered: BTreeMap<(u32, u32, (u32, u32, u32, u32, u32)), RenderedShadow>,
 }
 
 impl Shadow {
-    pub fn draw(&mut self, pixmap: &mut PixmapMut, scale: u32, active: bool, part: ShadowPart) {
-        let cache = &mut self.part_cache[part.index()];
+    /// Draw the shadow part, `shadow_size` is the logical thickness of the shadow, the visible
+    /// border is not included.
+    pub fn draw(
+        &mut self,
+        pixmap: &mut PixmapMut,
+        shadow_size: u32,
+        scale: u32,
+        params: ShadowParams,
+        part: ShadowPart,
+    ) {
+        // Nothing to draw when the border only has room for the visible border.
+        if shadow_size == 0 {
+            return;
+        }
 
-        if let Some(cache_value) = cache {
-            if !cache_value.matches(&pixmap.as_ref(), scale, active) {
-                *cache = None;
-            }
+        let [cache, previous] = &mut self.part_cache[part.index()];
+        let matches = |cache_value: &Option<CachedPart>| {
+            cache_value.as_ref().is_some_and(|cache_value| {
+                cache_value.matches(&pixmap.as_ref(), shadow_size, scale, &params)
+            })
+        };
+
+        if !matches(cache) {
+            // Keep the replaced look, the window usually goes back to it.
+            mem::swap(cache, previous);
         }
 
-        if cache.is_none() {
+        if !matches(cache) {
             let rendered = self
                 .rendered
-                .entry((scale, active))
-                .or_insert_with(|| RenderedShadow::new(scale, active));
+                .entry((shadow_size, scale, params.key()))
+                .or_insert_with(|| RenderedShadow::new(shadow_size, scale, &params));
 
             *cache = Some(CachedPart::new(
                 &pixmap.as_ref(),
                 rendered,
+                shadow_size,
                 scale,
-                active,
+                params,
                 part,
             ));
         }
@@ -426,35 +531,220 @@ impl Shadow {
 pub struct ShadowSurface {
     pub surface: WlSurface,
     pub subsurface: WlSubsurface,
+    /// Maps the buffer to the logical size, only when the compositor has `wp_viewporter`
+    pub viewport: Option<WpViewport>,
     pub x: i32,
     pub y: i32,
     pub width: u32,
     pub height: u32,
+    /// What the attached buffer shows, `None` when it must be redrawn
+    pub(crate) content: Option<ShadowContent>,
+    /// Whether a buffer is attached, a shrunk pool forgets the content but the old buffer stays
+    /// attached until the next draw
+    pub(crate) attached: bool,
 }
 
-#[derive(Debug)]
+/// The inputs of a drawn shadow surface, the surface is only redrawn and damaged when they
+/// change
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) struct ShadowContent {
+    pub width: u32,
+    pub height: u32,
+    pub scale: u32,
+    pub border_size: u32,
+    /// `None` when the part only has the visible border
+    pub params: Option<ShadowParams>,
+    pub border_color: Color,
+}
+
+/// Which sides of the window have a drop shadow
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum ShadowStyle {
+    /// Shadow around all sides, like libadwaita
+    #[default]
+    Adwaita,
+    /// Material design like elevation, only the bottom side has a shadow
+    Material,
+}
+
+impl ShadowStyle {
+    pub(crate) fn has_part(&self, part: ShadowPart) -> bool {
+        match self {
+            ShadowStyle::Adwaita => true,
+            ShadowStyle::Material => part == ShadowPart::Bottom,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Theme {
-    border_color: Color,
+    /// `None` follows the system color scheme
+    border_color: Option<Color>,
+    /// The visible border color of the resize edge under the pointer, `None` follows the system
+    /// color scheme
+    edge_highlight: Option<Color>,
+    shadow_color: Color,
+    style: ShadowStyle,
+    inactive_blur: f32,
+    falloff: Option<Falloff>,
+    corner_radius: u32,
+}
+
+/// A custom shadow falloff, `None` in the theme uses the libadwaita look
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct Falloff {
+    radius: f32,
+    active_opacity: f32,
+    inactive_opacity: f32,
 }
 
 impl Theme {
+    /// The libadwaita look, the border color follows the system color scheme
     pub fn auto() -> Self {
-        let border_color = if Self::prefer_dark() {
-            Self::dark()
+        Self {
+            border_color: None,
+            edge_highlight: None,
+            shadow_color: Color::BLACK,
+            style: ShadowStyle::default(),
+            inactive_blur: 1.0,
+            corner_radius: CORNER_RADIUS,
+            falloff: None,
+        }
+    }
+
+    /// The [`Theme::auto`] theme with a custom shadow
+    ///
+    /// `radius` is in logical pixels, it is the distance from the window where the shadow fades
+    /// to about a third of its opacity. The opacities are the shadow alpha next to the window,
+    /// in `0.0..=1.0`. The alpha of `color` is ignored.
+    pub fn custom(color: Color, radius: f32, active_opacity: f32, inactive_opacity: f32) -> Self {
+        fn opacity(opacity: f32) -> f32 {
+            if opacity.is_finite() {
+                opacity.clamp(0.0, 1.0)
+            } else {
+                0.0
+            }
+        }
+
+        let radius = if radius.is_finite() {
+            radius.max(1.0)
         } else {
-            Self::light()
+            1.0
         };
 
-        Self { border_color }
+        Self {
+            shadow_color: color,
+            falloff: Some(Falloff {
+                radius,
+                active_opacity: opacity(active_opacity),
+                inactive_opacity: opacity(inactive_opacity),
+            }),
+            ..Self::auto()
+        }
+    }
+
+    /// Use the `style` to decide which sides have a shadow
+    pub fn with_style(mut self, style: ShadowStyle) -> Self {
+        self.style = style;
+        self
+    }
+
+    /// The [`Theme::auto`] theme with the [`ShadowStyle::Material`] style
+    pub fn material() -> Self {
+        Self::auto().with_style(ShadowStyle::Material)
+    }
+
+    pub fn style(&self) -> ShadowStyle {
+        self.style
+    }
+
+    /// Make the shadow of the inactive window larger and softer, `blur` stretches the shadow
+    /// falloff and is clamped to at least 1.0, which is the default look
+    ///
+    /// The shadow is cut at the border, so a larger border size may be needed to show all of it.
+    pub fn with_inactive_blur(mut self, blur: f32) -> Self {
+        self.inactive_blur = if blur.is_finite() { blur.max(1.0) } else { 1.0 };
+        self
+    }
+
+    /// Set the logical radius of the rounded window corners the shadow follows, default is 10
+    ///
+    /// It is clamped between the visible border size, which gives square corners, and the
+    /// shadow size.
+    pub fn with_corner_radius(mut self, radius: u32) -> Self {
+        self.corner_radius = radius.clamp(VISIBLE_BORDER_SIZE, MAX_CORNER_RADIUS);
+        self
+    }
+
+    /// Set the visible border color of the resize edge under the pointer, default is a darker
+    /// border color in light mode and a lighter one in dark mode
+    pub fn with_edge_highlight(mut self, color: Color) -> Self {
+        self.edge_highlight = Some(color);
+        self
     }
 
-    pub fn border_paint(&self) -> Paint {
+    /// The logical radius of the rounded window corners
+    pub fn corner_radius(&self) -> u32 {
+        self.corner_radius
+    }
+
+    /// The falloff stretch of the shadow
+    fn blur(&self, active: bool) -> f32 {
+        if active {
+            1.0
+        } else {
+            self.inactive_blur
+        }
+    }
+
+    /// The shadow look of the active or inactive window
+    pub(crate) fn shadow_params(&self, active: bool) -> ShadowParams {
+        let (opacity, falloff, offset) = match self.falloff {
+            None if active => SHADOW_PARAMS_ACTIVE,
+            None => SHADOW_PARAMS_INACTIVE,
+            Some(falloff) if active => (falloff.active_opacity, 1.0 / falloff.radius, 0.0),
+            Some(falloff) => (falloff.inactive_opacity, 1.0 / falloff.radius, 0.0),
+        };
+
+        ShadowParams {
+            color: self.shadow_color.to_color_u8(),
+            opacity,
+            falloff: falloff / self.blur(active),
+            offset,
+            corner_radius: self.corner_radius,
+        }
+    }
+
+    /// The paint of the visible border in `color`, such as the border or the highlight color
+    pub(crate) fn border_paint_for(color: Color) -> Paint<'static> {
         Paint {
-            shader: Shader::SolidColor(self.border_color),
+            shader: Shader::SolidColor(color),
             ..Default::default()
         }
     }
 
+    /// The visible border color, it follows the color scheme when the theme has no color
+    pub(crate) fn border_color(&self, color_scheme: ColorScheme) -> Color {
+        self.border_color.unwrap_or_else(|| {
+            if color_scheme.is_dark() {
+                Self::dark()
+            } else {
+                Self::light()
+            }
+        })
+    }
+
+    /// The visible border color of the resize edge under the pointer
+    pub(crate) fn edge_highlight_color(&self, color_scheme: ColorScheme) -> Color {
+        self.edge_highlight.unwrap_or_else(|| {
+            if color_scheme.is_dark() {
+                Color::from_rgba8(120, 120, 120, 255)
+            } else {
+                Color::from_rgba8(150, 150, 150, 255)
+            }
+        })
+    }
+
     fn light() -> Color {
         Color::from_rgba8(220, 220, 220, 255)
     }
@@ -463,25 +753,23 @@ impl Theme {
     fn dark() -> Color {
         Color::from_rgba8(58, 58, 58, 255)
     }
+}
 
-    fn prefer_dark() -> bool {
-        // outputs something like: `variant       variant          uint32 1`
-        let stdout = Command::new("dbus-send")
-            .arg("--reply-timeout=100")
-            .arg("--print-reply=literal")
-            .arg("--dest=org.freedesktop.portal.Desktop")
-            .arg("/org/freedesktop/portal/desktop")
-            .arg("org.freedesktop.portal.Settings.Read")
-            .arg("string:org.freedesktop.appearance")
-            .arg("string:color-scheme")
-            .output()
-            .ok()
-            .and_then(|out| String::from_utf8(out.stdout).ok());
-
-        if matches!(stdout, Some(ref s) if s.is_empty()) {
-            warn!("XDG Settings Portal did not return response in time: timeout: 100ms, key: color-scheme");
-        }
-
-        matches!(stdout, Some(s) if s.trim().ends_with("uint32 1"))
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn corner_radius_is_clamped() {
+        let theme = Theme::auto();
+        assert_eq!(
+            theme.clone().with_corner_radius(0).corner_radius(),
+            VISIBLE_BORDER_SIZE
+        );
+        assert_eq!(theme.clone().with_corner_radius(12).corner_radius(), 12);
+        assert_eq!(
+            theme.with_corner_radius(u32::MAX).corner_radius(),
+            MAX_CORNER_RADIUS
+        );
     }
 }
@@ -0,0 +1,496 @@
+use std::fmt::{self, Debug, Formatter};
+
+use ab_glyph::{point, Font, FontArc, GlyphId, PxScale, ScaleFont};
+use smithay_client_toolkit::reexports::csd_frame::WindowState;
+use tiny_skia::{
+    Color, FillRule, LineCap, Mask, Paint, PathBuilder, PixmapMut, Rect, Shader, Stroke, Transform,
+};
+
+use crate::pointer::ButtonKind;
+use crate::renderer::{ButtonRect, HeaderContext, HeaderRenderer};
+use crate::style::HeaderTheme;
+use crate::SPACER_SIZE;
+
+/// The titlebar button size, like the 16px icon with the padding of the gtk titlebutton
+const BUTTON_SIZE: f32 = 24.0;
+/// The gap between the buttons and around the buttons of a side
+const BUTTON_SPACING: f32 = 6.0;
+/// The size of the button glyphs, they are drawn inside a square of this size
+const GLYPH_SIZE: f32 = 8.0;
+const GLYPH_STROKE_WIDTH: f32 = 1.5;
+/// The pixel size of the title, the gtk title is 11pt bold
+const TITLE_SIZE: f32 = 14.0;
+const SUBTITLE_SIZE: f32 = 11.0;
+const ELLIPSIS: char = '…';
+
+/// Draws the header bar with tiny-skia only, it is the header bar of the frames built without
+/// the `gtk` feature
+///
+/// The title is drawn with the font of [`SkiaHeaderRenderer::with_font_data`], without a font
+/// only the buttons are drawn. The colors follow the [`HeaderTheme`] of the frame, the unset
+/// ones follow the system color scheme.
+#[derive(Clone, Default)]
+pub struct SkiaHeaderRenderer {
+    font: Option<FontArc>,
+}
+
+impl Debug for SkiaHeaderRenderer {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SkiaHeaderRenderer")
+            .field("font", &self.font.is_some())
+            .finish()
+    }
+}
+
+impl SkiaHeaderRenderer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Draw the title with the TrueType or OpenType font `data`, such as a font file read by the
+    /// app
+    ///
+    /// # Errors
+    ///
+    /// if `data` is not a valid font
+    pub fn with_font_data(mut self, data: Vec<u8>) -> anyhow::Result<Self> {
+        self.font = Some(FontArc::try_from_vec(data)?);
+
+        Ok(self)
+    }
+}
+
+impl HeaderRenderer for SkiaHeaderRenderer {
+    fn render(&mut self, context: &HeaderContext, pixmap: &mut PixmapMut) -> Vec<ButtonRect> {
+        let colors = HeaderColors::new(context);
+        let scale = context.scale as f32;
+        let (width, height) = (pixmap.width() as f32, pixmap.height() as f32);
+
+        pixmap.fill(colors.background);
+
+        let (mut rects, title_start) =
+            layout_side(context.left_buttons, scale, width, height, false);
+        let (right_rects, title_end) =
+            layout_side(context.right_buttons, scale, width, height, true);
+        rects.extend(right_rects);
+
+        for rect in &rects {
+            draw_button(pixmap, rect, context, &colors, scale);
+        }
+
+        if let Some(font) = &self.font {
+            let title_space = (title_start, width - title_end);
+            draw_title(pixmap, font, context, &colors, scale, title_space);
+        }
+
+        rects
+    }
+}
+
+/// The colors of the header bar, the unset theme colors follow the color scheme
+struct HeaderColors {
+    background: Color,
+    foreground: Color,
+    button_hover: Color,
+    button_pressed: Color,
+}
+
+impl HeaderColors {
+    fn new(context: &HeaderContext) -> Self {
+        let fallback = if context.color_scheme.is_dark() {
+            HeaderTheme::dark()
+        } else {
+            HeaderTheme::light()
+        };
+        let theme = &context.header_theme;
+
+        let background = theme
+            .background
+            .or(fallback.background)
+            .unwrap_or(Color::WHITE);
+        let mut foreground = theme
+            .foreground_color()
+            .or(fallback.foreground)
+            .unwrap_or(Color::BLACK);
+        let button_hover = theme
+            .button_hover
+            .or(fallback.button_hover)
+            .unwrap_or(Color::TRANSPARENT);
+
+        // The pressed button is darker than the hovered one, like the gtk titlebutton.
+        let mut button_pressed = button_hover;
+        button_pressed.set_alpha(button_hover.alpha() * 2.0);
+
+        // An unfocused window is dimmed like the gtk backdrop state.
+        if !context.state.contains(WindowState::ACTIVATED) {
+            foreground.apply_opacity(0.5);
+        }
+
+        Self {
+            background,
+            foreground,
+            button_hover,
+            button_pressed,
+        }
+    }
+}
+
+/// Lay out the buttons of one side from its edge, return their rects and how far the buttons
+/// reach from the edge
+///
+/// The end side is in `pack_end` order, which starts from the end.
+fn layout_side(
+    kinds: &[ButtonKind],
+    scale: f32,
+    width: f32,
+    height: f32,
+    at_end: bool,
+) -> (Vec<ButtonRect>, f32) {
+    let size = BUTTON_SIZE * scale;
+    let y = ((height - size) / 2.0).max(0.0);
+    let mut offset = BUTTON_SPACING * scale;
+    let mut rects = Vec::new();
+
+    for &kind in kinds {
+        let advance = match kind {
+            // The skia header bar has no window icon, so it takes no room.
+            ButtonKind::Icon => continue,
+            // Spacers only take room, they can't be clicked.
+            ButtonKind::Spacer => SPACER_SIZE as f32 * scale,
+            _ => {
+                let x = if at_end {
+                    width - offset - size
+                } else {
+                    offset
+                };
+                rects.push(ButtonRect {
+                    kind,
+                    x: x.round() as i32,
+                    y: y.round() as i32,
+                    width: size.round() as u32,
+                    height: size.round() as u32,
+                });
+
+                size
+            }
+        };
+
+        offset += advance + BUTTON_SPACING * scale;
+    }
+
+    (rects, offset)
+}
+
+fn solid_paint(color: Color) -> Paint<'static> {
+    Paint {
+        shader: Shader::SolidColor(color),
+        ..Default::default()
+    }
+}
+
+/// Draw the hover or pressed background and the symbolic glyph of the button
+fn draw_button(
+    pixmap: &mut PixmapMut,
+    rect: &ButtonRect,
+    context: &HeaderContext,
+    colors: &HeaderColors,
+    scale: f32,
+) {
+    let size = rect.width as f32;
+    let (cx, cy) = (rect.x as f32 + size / 2.0, rect.y as f32 + size / 2.0);
+
+    let background = if context.pressed_button == Some(rect.kind) {
+        Some(colors.button_pressed)
+    } else if context.hovered_button == Some(rect.kind) {
+        Some(colors.button_hover)
+    } else {
+        None
+    };
+
+    if let Some(color) = background {
+        if let Some(circle) = PathBuilder::from_circle(cx, cy, size / 2.0) {
+            pixmap.fill_path(
+                &circle,
+                &solid_paint(color),
+                FillRule::Winding,
+                Transform::identity(),
+                None,
+            );
+        }
+    }
+
+    let half = GLYPH_SIZE * scale / 2.0;
+    let mut builder = PathBuilder::new();
+
+    match rect.kind {
+        ButtonKind::Close => {
+            builder.move_to(cx - half, cy - half);
+            builder.line_to(cx + half, cy + half);
+            builder.move_to(cx + half, cy - half);
+            builder.line_to(cx - half, cy + half);
+        }
+
+        ButtonKind::Minimize => {
+            builder.move_to(cx - half, cy + half / 2.0);
+            builder.line_to(cx + half, cy + half / 2.0);
+        }
+
+        ButtonKind::Maximize if context.state.contains(WindowState::MAXIMIZED) => {
+            // The restore glyph, a window in front of another one.
+            let front = half * 1.5;
+            if let Some(rect) = Rect::from_xywh(cx - half, cy + half - front, front, front) {
+                builder.push_rect(rect);
+            }
+            builder.move_to(cx + half - front, cy + half - front);
+            builder.line_to(cx + half - front, cy - half);
+            builder.line_to(cx + half, cy - half);
+            builder.line_to(cx + half, cy + half - (2.0 * half - front));
+            builder.line_to(cx - half + front, cy + half - (2.0 * half - front));
+        }
+
+        ButtonKind::Maximize => {
+            if let Some(rect) = Rect::from_xywh(cx - half, cy - half, 2.0 * half, 2.0 * half) {
+                builder.push_rect(rect);
+            }
+        }
+
+        ButtonKind::Menu => {
+            for y in [cy - half * 0.75, cy, cy + half * 0.75] {
+                builder.move_to(cx - half, y);
+                builder.line_to(cx + half, y);
+            }
+        }
+
+        ButtonKind::Spacer | ButtonKind::Icon => return,
+    }
+
+    let Some(path) = builder.finish() else {
+        return;
+    };
+
+    let stroke = Stroke {
+        width: GLYPH_STROKE_WIDTH * scale,
+        line_cap: LineCap::Round,
+        ..Default::default()
+    };
+
+    pixmap.stroke_path(
+        &path,
+        &solid_paint(colors.foreground),
+        &stroke,
+        Transform::identity(),
+        None,
+    );
+}
+
+/// Draw the title, and the subtitle below it, centered in the header bar or in the space between
+/// the buttons when it doesn't fit the center
+fn draw_title(
+    pixmap: &mut PixmapMut,
+    font: &FontArc,
+    context: &HeaderContext,
+    colors: &HeaderColors,
+    scale: f32,
+    (start, end): (f32, f32),
+) {
+    if end <= start {
+        return;
+    }
+
+    let Some(mut mask) = Mask::new(pixmap.width(), pixmap.height()) else {
+        return;
+    };
+
+    let title_scale = PxScale::from(TITLE_SIZE * scale);
+    let subtitle_scale = PxScale::from(SUBTITLE_SIZE * scale);
+    let line_height = |px_scale: PxScale| font.as_scaled(px_scale).height();
+
+    let mut lines = vec![(context.title, title_scale)];
+    if let Some(subtitle) = context.subtitle {
+        lines.push((subtitle, subtitle_scale));
+    }
+
+    // The lines are one block in the vertical middle of the header bar.
+    let block_height: f32 = lines
+        .iter()
+        .map(|(_, px_scale)| line_height(*px_scale))
+        .sum();
+    let mut top = (pixmap.height() as f32 - block_height) / 2.0;
+
+    for (text, px_scale) in lines {
+        let scaled_font = font.as_scaled(px_scale);
+        let (glyphs, text_width) = layout_line(font, px_scale, text, end - start);
+
+        // Centered in the header bar like gtk, pushed aside by the buttons when it doesn't fit.
+        let centered = (pixmap.width() as f32 - text_width) / 2.0;
+        let x = centered.clamp(start, (end - text_width).max(start));
+        let baseline = top + scaled_font.ascent();
+
+        for (glyph_id, glyph_x) in glyphs {
+            let glyph = glyph_id.with_scale_and_position(px_scale, point(x + glyph_x, baseline));
+            let Some(outlined) = font.outline_glyph(glyph) else {
+                continue;
+            };
+
+            let bounds = outlined.px_bounds();
+            let (mask_width, mask_height) = (mask.width() as i64, mask.height() as i64);
+            let data = mask.data_mut();
+
+            outlined.draw(|glyph_x, glyph_y, coverage| {
+                let x = bounds.min.x as i64 + glyph_x as i64;
+                let y = bounds.min.y as i64 + glyph_y as i64;
+                if x < start as i64 || x >= end as i64 || y < 0 || y >= mask_height {
+                    return;
+                }
+
+                let index = (y * mask_width + x) as usize;
+                let coverage = (coverage.clamp(0.0, 1.0) * u8::MAX as f32) as u8;
+                data[index] = data[index].max(coverage);
+            });
+        }
+
+        top += scaled_font.height();
+    }
+
+    let Some(rect) = Rect::from_xywh(0.0, 0.0, pixmap.width() as f32, pixmap.height() as f32)
+    else {
+        return;
+    };
+
+    pixmap.fill_rect(
+        rect,
+        &solid_paint(colors.foreground),
+        Transform::identity(),
+        Some(&mask),
+    );
+}
+
+/// Lay out the glyphs of the text on one line, the text is ellipsized at the end when it is
+/// wider than `max_width`
+///
+/// Return the glyphs with their offsets from the line start, and the line width.
+fn layout_line(
+    font: &FontArc,
+    px_scale: PxScale,
+    text: &str,
+    max_width: f32,
+) -> (Vec<(GlyphId, f32)>, f32) {
+    let scaled_font = font.as_scaled(px_scale);
+    let ellipsis = scaled_font.glyph_id(ELLIPSIS);
+    let ellipsis_width = scaled_font.h_advance(ellipsis);
+
+    let mut glyphs = Vec::new();
+    let mut caret = 0.0;
+    // The glyphs up to this one still leave room for the ellipsis.
+    let mut ellipsis_at = None;
+    let mut last_glyph = None;
+
+    for c in text.chars().filter(|c| !c.is_control()) {
+        let glyph_id = scaled_font.glyph_id(c);
+        if let Some(last_glyph) = last_glyph {
+            caret += scaled_font.kern(last_glyph, glyph_id);
+        }
+
+        let advance = scaled_font.h_advance(glyph_id);
+        if caret + advance > max_width {
+            let (len, width) = ellipsis_at.unwrap_or((0, 0.0));
+            glyphs.truncate(len);
+            glyphs.push((ellipsis, width));
+
+            return (glyphs, width + ellipsis_width);
+        }
+
+        glyphs.push((glyph_id, caret));
+        caret += advance;
+        last_glyph = Some(glyph_id);
+
+        if caret + ellipsis_width <= max_width {
+            ellipsis_at = Some((glyphs.len(), caret));
+        }
+    }
+
+    (glyphs, caret)
+}
+
+#[cfg(test)]
+mod tests {
+    use smithay_client_toolkit::reexports::csd_frame::WindowManagerCapabilities;
+    use tiny_skia::Pixmap;
+
+    use super::*;
+    use crate::ColorScheme;
+
+    fn context<'a>(
+        left_buttons: &'a [ButtonKind],
+        right_buttons: &'a [ButtonKind],
+    ) -> HeaderContext<'a> {
+        HeaderContext {
+            scale: 2.0,
+            title: "title",
+            subtitle: None,
+            left_buttons,
+            right_buttons,
+            hovered_button: None,
+            pressed_button: None,
+            state: WindowState::ACTIVATED,
+            wm_capabilities: WindowManagerCapabilities::all(),
+            color_scheme: ColorScheme::Light,
+            header_theme: HeaderTheme::default(),
+        }
+    }
+
+    #[test]
+    fn buttons_are_laid_out_from_both_edges() {
+        let right_buttons = [ButtonKind::Close, ButtonKind::Spacer, ButtonKind::Maximize];
+        let left_buttons = [ButtonKind::Icon, ButtonKind::Menu];
+        let context = context(&left_buttons, &right_buttons);
+        let mut pixmap = Pixmap::new(800, 100).unwrap();
+
+        let rects = SkiaHeaderRenderer::new().render(&context, &mut pixmap.as_mut());
+
+        let rect = |kind| rects.iter().find(|rect| rect.kind == kind).copied();
+        let button = |kind, x| {
+            Some(ButtonRect {
+                kind,
+                x,
+                y: 26,
+                width: 48,
+                height: 48,
+            })
+        };
+
+        // The icon and the spacer have no rect, the spacer still takes room.
+        assert_eq!(rects.len(), 3);
+        assert_eq!(rect(ButtonKind::Menu), button(ButtonKind::Menu, 12));
+        assert_eq!(
+            rect(ButtonKind::Close),
+            button(ButtonKind::Close, 800 - 12 - 48)
+        );
+        assert_eq!(
+            rect(ButtonKind::Maximize),
+            button(ButtonKind::Maximize, 800 - 12 - 48 - 12 - 24 - 12 - 48)
+        );
+    }
+
+    #[test]
+    fn background_follows_the_theme() {
+        let mut context = context(&[], &[]);
+        context.header_theme.background = Some(Color::from_rgba8(255, 0, 0, 255));
+        let mut pixmap = Pixmap::new(100, 50).unwrap();
+
+        SkiaHeaderRenderer::new().render(&context, &mut pixmap.as_mut());
+
+        let pixel = pixmap.pixel(50, 25).unwrap();
+        assert_eq!(
+            (pixel.red(), pixel.green(), pixel.blue(), pixel.alpha()),
+            (255, 0, 0, 255)
+        );
+    }
+
+    #[test]
+    fn invalid_font_data_is_rejected() {
+        assert!(SkiaHeaderRenderer::new()
+            .with_font_data(b"not a font".to_vec())
+            .is_err());
+    }
+}
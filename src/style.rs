@@ -0,0 +1,125 @@
+#[cfg(feature = "gtk")]
+use gtk::{
+    glib::IsA,
+    prelude::{Cast, ContainerExt, CssProviderExt, GtkSettingsExt, StyleContextExt, WidgetExt},
+    Container, CssProvider, Settings, Widget,
+};
+use tiny_skia::Color;
+#[cfg(feature = "gtk")]
+use tracing::warn;
+
+/// The colors of the header bar, every `None` color follows the gtk theme
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct HeaderTheme {
+    /// Background color of the header bar
+    pub background: Option<Color>,
+
+    /// Color of the title and button icons, `None` picks a readable color for `background`, or
+    /// uses the gtk theme
+    pub foreground: Option<Color>,
+
+    /// Background color of the hovered button
+    pub button_hover: Option<Color>,
+}
+
+impl HeaderTheme {
+    /// Follow the gtk theme, this is the default
+    pub fn auto() -> Self {
+        Self::default()
+    }
+
+    /// Fixed colors which aim to replicate the Adwaita theme
+    pub fn light() -> Self {
+        Self {
+            background: Some(Color::from_rgba8(235, 235, 235, 255)),
+            foreground: Some(Color::from_rgba8(46, 52, 54, 255)),
+            button_hover: Some(Color::from_rgba8(0, 0, 0, 26)),
+        }
+    }
+
+    /// Fixed colors which aim to replicate the Adwaita-dark theme
+    pub fn dark() -> Self {
+        Self {
+            background: Some(Color::from_rgba8(48, 48, 48, 255)),
+            foreground: Some(Color::from_rgba8(238, 238, 236, 255)),
+            button_hover: Some(Color::from_rgba8(255, 255, 255, 26)),
+        }
+    }
+
+    /// The color of the title and button icons, `None` uses the gtk theme
+    pub(crate) fn foreground_color(&self) -> Option<Color> {
+        self.foreground
+            .or_else(|| self.background.map(contrast_color))
+    }
+}
+
+/// Format the color as a gtk css `rgba()` color
+#[cfg(feature = "gtk")]
+pub fn css_color(color: Color) -> String {
+    let color = color.to_color_u8();
+
+    format!(
+        "rgba({}, {}, {}, {:.3})",
+        color.red(),
+        color.green(),
+        color.blue(),
+        color.alpha() as f32 / u8::MAX as f32
+    )
+}
+
+/// Pick a light or dark foreground color which is readable on the `background`
+pub fn contrast_color(background: Color) -> Color {
+    fn linear(channel: f32) -> f32 {
+        if channel <= 0.04045 {
+            channel / 12.92
+        } else {
+            ((channel + 0.055) / 1.055).powf(2.4)
+        }
+    }
+
+    // WCAG relative luminance
+    let luminance = 0.2126 * linear(background.red())
+        + 0.7152 * linear(background.green())
+        + 0.0722 * linear(background.blue());
+
+    // Above this luminance black text has more contrast than white text
+    if luminance > 0.179 {
+        Color::from_rgba8(0, 0, 0, 204)
+    } else {
+        Color::from_rgba8(255, 255, 255, 255)
+    }
+}
+
+/// Apply the css to the widget only, with application priority so it overrides the gtk theme
+#[cfg(feature = "gtk")]
+pub fn apply_widget_css(widget: &impl IsA<Widget>, css: &str) {
+    let provider = CssProvider::new();
+    if let Err(err) = provider.load_from_data(css.as_bytes()) {
+        warn!(%err, css, "load widget css failed");
+
+        return;
+    }
+
+    widget
+        .style_context()
+        .add_provider(&provider, gtk::STYLE_PROVIDER_PRIORITY_APPLICATION);
+}
+
+/// Add the provider to the widget and all its descendants
+#[cfg(feature = "gtk")]
+pub fn add_provider_recursive(widget: &impl IsA<Widget>, provider: &CssProvider, priority: u32) {
+    widget.style_context().add_provider(provider, priority);
+
+    if let Some(container) = widget.dynamic_cast_ref::<Container>() {
+        // `forall` also visits the internal children, such as the title box of the header bar
+        container.forall(|child| add_provider_recursive(child, provider, priority));
+    }
+}
+
+/// The dark variant of the current gtk theme, gtk caches the parsed theme
+#[cfg(feature = "gtk")]
+pub fn dark_theme_provider() -> Option<CssProvider> {
+    let theme_name = Settings::default()?.gtk_theme_name()?;
+
+    CssProvider::named(&theme_name, Some("dark"))
+}